@@ -0,0 +1,71 @@
+use crate::config::{Config, ConfigError, Profile};
+use crate::opt::{AccountsCommand, KeyringCommand};
+use std::path::Path;
+use ttr_launcher_oxide::login;
+
+/// Runs an `accounts` subcommand against the config file at `path`.
+pub fn accounts(
+    command: AccountsCommand,
+    config: &mut Config,
+    path: &Path,
+) -> Result<(), ConfigError> {
+    match command {
+        AccountsCommand::Add {
+            name,
+            username,
+            keyring,
+            install_dir,
+        } => {
+            // Keep the launch extras of a profile that is being replaced
+            let launch = config
+                .profiles
+                .remove(&name)
+                .map(|p| p.launch)
+                .unwrap_or_default();
+            config.profiles.insert(
+                name,
+                Profile {
+                    username,
+                    keyring,
+                    install_dir,
+                    launch,
+                },
+            );
+            config.save(path)
+        }
+        AccountsCommand::Remove { name } => {
+            if config.profiles.remove(&name).is_none() {
+                return Err(ConfigError::UnknownProfile(name));
+            }
+            config.save(path)
+        }
+        AccountsCommand::List => {
+            for (name, profile) in &config.profiles {
+                print!("{}: {}", name, profile.username);
+                if profile.keyring {
+                    print!(" (keyring)");
+                }
+                if let Some(install_dir) = &profile.install_dir {
+                    print!(" in {}", install_dir.display());
+                }
+                println!();
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Runs a `keyring` subcommand.
+pub fn keyring(command: KeyringCommand, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        KeyringCommand::Forget { username, profile } => {
+            let username = match profile {
+                Some(name) => config.profile(&name)?.username.clone(),
+                None => username.expect("username is required without a profile"),
+            };
+            login::reset_keyring(&username)?;
+            println!("Forgot the password for {}", username);
+            Ok(())
+        }
+    }
+}
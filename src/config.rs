@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use ttr_launcher_oxide::launch::LaunchExtras;
+
+pub use error::*;
+
+/// Persistent launcher settings, stored as TOML.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(default, skip_serializing_if = "Endpoints::is_empty")]
+    pub endpoints: Endpoints,
+}
+
+/// A named account.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Profile {
+    pub username: String,
+    /// Whether the system keyring holds the password for this account.
+    #[serde(default)]
+    pub keyring: bool,
+    /// Overrides the installation directory for this account.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub install_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "LaunchExtras::is_empty")]
+    pub launch: LaunchExtras,
+}
+
+/// Overrides for the servers the launcher talks to, such as a local caching mirror.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Endpoints {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub login_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest_url: Option<String>,
+    /// CDN mirrors, tried in order until one of them has the file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+}
+
+impl Endpoints {
+    pub fn is_empty(&self) -> bool {
+        self.login_url.is_none() && self.manifest_url.is_none() && self.mirrors.is_empty()
+    }
+}
+
+/// Location of the config file when `--config` is not given.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("ttr-launcher-oxide").join("config.toml"))
+}
+
+impl Config {
+    /// Reads the config at `path`. A missing file is an empty config.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Ok(toml::from_str(text.as_str())?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the config to `path`, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&Profile, ConfigError> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| ConfigError::UnknownProfile(name.to_string()))
+    }
+}
+
+mod error {
+    use std::fmt::{Debug, Formatter, Result as FmtResult};
+
+    #[derive(Debug)]
+    pub enum ConfigError {
+        IO(std::io::Error),
+        Parsing(toml::de::Error),
+        Serializing(toml::ser::Error),
+        UnknownProfile(String),
+        /// Every profile was requested, but the config file has none.
+        NoProfiles,
+    }
+
+    impl std::error::Error for ConfigError {}
+    impl std::fmt::Display for ConfigError {
+        fn fmt(&self, f: &mut Formatter) -> FmtResult {
+            match self {
+                Self::IO(inner) => write!(f, "An IO error occurred: {}", inner),
+                Self::Parsing(inner) => write!(f, "The config file is malformed: {}", inner),
+                Self::Serializing(inner) => write!(f, "Failed to write the config: {}", inner),
+                Self::UnknownProfile(name) => write!(f, "No profile named '{}'", name),
+                Self::NoProfiles => {
+                    write!(f, "No profiles are configured, add one with `accounts add`")
+                }
+            }
+        }
+    }
+
+    impl From<std::io::Error> for ConfigError {
+        fn from(err: std::io::Error) -> Self {
+            Self::IO(err)
+        }
+    }
+
+    impl From<toml::de::Error> for ConfigError {
+        fn from(err: toml::de::Error) -> Self {
+            Self::Parsing(err)
+        }
+    }
+
+    impl From<toml::ser::Error> for ConfigError {
+        fn from(err: toml::ser::Error) -> Self {
+            Self::Serializing(err)
+        }
+    }
+}
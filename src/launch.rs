@@ -1,44 +1,78 @@
 use crate::login::LoginToken;
-use crate::opt::Options;
+pub use error::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
 use tokio::process::Command;
 
-pub async fn launch(options: &Options, token: LoginToken) {
-    if let Err(err) = std::env::set_current_dir(&options.install_dir) {
-        eprintln!("Failed to set working directory.\n{}", err);
-        return;
+#[cfg(target_os = "linux")]
+const ENGINE_NAME: &str = "TTREngine";
+
+// -- TODO verify this works --
+#[cfg(target_os = "windows")]
+const ENGINE_NAME: &str = "TTREngine.exe";
+
+#[cfg(target_os = "macos")]
+const ENGINE_NAME: &str = "Toontown Rewritten";
+// ----
+
+/// Extra arguments and environment variables passed to the game engine.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LaunchExtras {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl LaunchExtras {
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty() && self.env.is_empty()
     }
-    #[cfg(target_os = "linux")]
-    let mut command = Command::new("./TTREngine");
+}
 
-    // -- TODO verify this works --
-    #[cfg(target_os = "windows")]
-    let mut command = Command::new("./TTREngine.exe");
+/// Path of the game engine executable inside an installation directory.
+pub fn engine_path(install_dir: &Path) -> PathBuf {
+    install_dir.join(ENGINE_NAME)
+}
 
-    #[cfg(target_os = "macos")]
-    let mut command = Command::new("./Toontown Rewritten");
-    // ----
+/// Starts the game engine in `install_dir` with the given login token and waits for it to exit.
+pub async fn launch(
+    install_dir: &Path,
+    token: LoginToken,
+    extras: &LaunchExtras,
+) -> Result<ExitStatus, LaunchError> {
+    let mut command = Command::new(engine_path(install_dir));
+    command.current_dir(install_dir);
+    command.args(&extras.args);
+    command.envs(&extras.env);
 
     // Set the environment variables the engine expects
     command.env("TTR_GAMESERVER", token.server);
     command.env("TTR_PLAYCOOKIE", token.cookie);
 
     // spawn and await its completion
-    match command.spawn() {
-        Ok(handle) => match handle.await {
-            Ok(status) => {
-                if status.success() {
-                    println!("TTREngine exited normally");
-                } else {
-                    eprintln!(
-                        "TTREngine executed abnormally! Exit code: {:?}",
-                        status.code()
-                    );
-                }
-            }
-            Err(err) => {
-                eprintln!("TTREngine executed really abnormally!\n{}", err);
+    let handle = command.spawn().map_err(LaunchError::Spawn)?;
+    handle.await.map_err(LaunchError::Wait)
+}
+
+mod error {
+    use std::fmt::{Formatter, Result as FmtResult};
+
+    #[derive(Debug)]
+    pub enum LaunchError {
+        Spawn(std::io::Error),
+        Wait(std::io::Error),
+    }
+
+    impl std::error::Error for LaunchError {}
+    impl std::fmt::Display for LaunchError {
+        fn fmt(&self, f: &mut Formatter) -> FmtResult {
+            match self {
+                Self::Spawn(inner) => write!(f, "Failed to launch TTREngine: {}", inner),
+                Self::Wait(inner) => write!(f, "TTREngine executed really abnormally: {}", inner),
             }
-        },
-        Err(err) => eprintln!("Failed to launch TTREngine!\n{}", err),
+        }
     }
 }
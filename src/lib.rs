@@ -0,0 +1,22 @@
+//! Updater, login and launch logic for Toontown Rewritten.
+//!
+//! None of these modules print anything themselves. Progress is reported through
+//! callbacks ([`update::UpdateEvent`], [`login::LoginHandler`]) so the caller decides how
+//! (and whether) to present it.
+
+#![deny(unreachable_code, unreachable_patterns, unused_assignments, unused_must_use, unused_extern_crates)]
+#![warn(unused_qualifications, unused_import_braces)]
+
+pub mod launch;
+pub mod login;
+pub mod update;
+
+/// Checks that `url` is an absolute HTTP or HTTPS URL. Endpoints come from the command line,
+/// environment and config files, so the error says what is wrong with them.
+fn check_url(url: &str) -> Result<(), String> {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => Ok(()),
+        Ok(_) => Err(format!("{} is not an HTTP or HTTPS URL", url)),
+        Err(err) => Err(format!("{} is not a valid URL: {}", url, err)),
+    }
+}
@@ -1,185 +1,186 @@
 use std::str::FromStr;
 
+pub use error::*;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use tokio::time::Duration;
 
-const LOGIN_URL: &'static str = "https://www.toontownrewritten.com/api/login?format=json";
-const SERVICE_NAME: &'static str = "ttr-launcher-oxide";
+/// The official login endpoint, used unless another is configured.
+pub const DEFAULT_LOGIN_URL: &str = "https://www.toontownrewritten.com/api/login?format=json";
+const SERVICE_NAME: &str = "ttr-launcher-oxide";
 
-pub fn reset_keyring(username: &str) {
+/// Receives progress from [`login`] and supplies input it cannot get on its own.
+pub trait LoginHandler {
+    /// Called whenever the login makes progress worth reporting.
+    fn event(&mut self, _event: LoginEvent) {}
+
+    /// Called when the account requires a two factor code. Returning `None` aborts the login.
+    fn totp_code(&mut self) -> Option<String>;
+}
+
+#[derive(Debug)]
+pub enum LoginEvent {
+    /// The account is waiting in the login queue.
+    Queued { position: u32, eta: u32 },
+    /// The supplied two factor code was not accepted; another will be requested.
+    TotpRejected,
+    /// The password could not be saved in the keyring. The login carries on regardless.
+    PasswordNotSaved(keyring::KeyringError),
+}
+
+/// Forgets any password held in the keyring for `username`.
+pub fn reset_keyring(username: &str) -> Result<(), LoginError> {
     let keyring = keyring::Keyring::new(SERVICE_NAME, username);
-    if let Err(err) = keyring.delete_password() {
-        eprintln!("Failed to delete password!\n{}", err);
-    }
+    Ok(keyring.delete_password()?)
+}
+
+/// Checks that `url` is a login endpoint that can be requested.
+pub fn check_url(url: &str) -> Result<(), LoginError> {
+    crate::check_url(url).map_err(LoginError::InvalidUrl)
 }
 
-pub async fn login(
-    username: String,
+/// Logs in to `username` at the login endpoint `url`. If `password` is `None`, it is retrieved
+/// from the keyring.
+pub async fn login<H: LoginHandler>(
+    url: &str,
+    username: &str,
     password: Option<String>,
     save_password: bool,
-) -> Option<LoginToken> {
+    handler: &mut H,
+) -> Result<LoginToken, LoginError> {
+    check_url(url)?;
+    let keyring = keyring::Keyring::new(SERVICE_NAME, username);
     let password = match password {
         Some(p) => {
             if save_password {
-                let keyring = keyring::Keyring::new(SERVICE_NAME, username.as_str());
                 if let Err(err) = keyring.set_password(p.as_str()) {
-                    eprintln!("Failed to save password in keyring.\n{}", err);
-                }
-            }
-            p.to_string()
-        }
-        None => {
-            let keyring = keyring::Keyring::new(SERVICE_NAME, username.as_str());
-            match keyring.get_password() {
-                Ok(pass) => pass,
-                Err(err) => {
-                    eprintln!("Failed to retrieve stored password from keyring.\n{}", err);
-                    return None;
+                    handler.event(LoginEvent::PasswordNotSaved(err));
                 }
             }
+            p
         }
+        None => keyring.get_password()?,
     };
     let credentials = Credentials {
         username: username.to_string(),
         password,
     };
-    let client = reqwest::Client::new();
-    let initial_request = client
-        .post(LOGIN_URL)
-        .form(&credentials)
-        .build()
-        .expect("Forming request");
-
-    match client.execute(initial_request).await {
-        Ok(res) => {
-            let response_result = res.json::<LoginResponse>().await;
-            handle_login_response(&client, response_result).await
-        }
-        Err(err) => {
-            eprintln!("An error occurred while executing the request.\n{}", err);
-            None
-        }
-    }
+    let api = Api {
+        client: Client::new(),
+        url,
+    };
+    let response = post(&api, &credentials).await?;
+    dispatch_2fa_possible(&api, response, handler).await
 }
 
-macro_rules! response_result_none_return {
-    ($response_result:expr) => {{
-        if let Err(err) = $response_result {
-            eprintln!("Login error\n{}", err);
-            return None;
-        }
-        $response_result.unwrap()
-    }};
+/// The login endpoint every request of a login goes to.
+struct Api<'a> {
+    client: Client,
+    url: &'a str,
 }
 
-async fn handle_login_response(
-    client: &Client,
-    response_result: reqwest::Result<LoginResponse>,
-) -> Option<LoginToken> {
-    let response = response_result_none_return!(response_result);
-    dispatch_2fa_possible(client, response).await
+async fn post<T: Serialize>(api: &Api<'_>, form: &T) -> Result<LoginResponse, LoginError> {
+    let request = api.client.post(api.url).form(form).build()?;
+    let resp = api.client.execute(request).await?;
+    let status = resp
+        .error_for_status_ref()
+        .map(|_| ())
+        .map_err(LoginError::from);
+    let text = resp.text().await?;
+    match serde_json::from_str(text.as_str()) {
+        Ok(response) => Ok(response),
+        // A server that is down answers with an error page, which isn't the login's fault
+        Err(err) => {
+            status?;
+            Err(err.into())
+        }
+    }
 }
 
-async fn dispatch_no_2fa(client: &Client, response: LoginResponse) -> Option<LoginToken> {
+async fn dispatch_no_2fa<H: LoginHandler>(
+    api: &Api<'_>,
+    response: LoginResponse,
+    handler: &mut H,
+) -> Result<LoginToken, LoginError> {
     match response.success {
-        LoginResult::Success => Some(LoginToken {
-            server: response.gameserver.unwrap(),
-            cookie: response.cookie.unwrap(),
+        LoginResult::Success => response.token(),
+        // 2FA is not possible at this point
+        LoginResult::Partial => Err(LoginError::MalformedResponse { field: "success" }),
+        LoginResult::Failure => Err(LoginError::BadCredentials {
+            banner: response.banner,
         }),
-        LoginResult::Partial => panic!("2FA asserted not possible, but came back partial!"),
-        LoginResult::Failure => None,
         LoginResult::Delayed => {
-            let eta = u32::from_str(response.eta.as_ref().unwrap()).expect("parsing eta number");
-            let position = u32::from_str(response.position.as_ref().unwrap())
-                .expect("parsing position number");
-            let res = queue(&client, response.queue_token.unwrap(), eta, position).await;
-            if res.is_none() {
-                eprintln!("An error occurred while moving through the queue.");
-                return None;
-            }
-            res
+            let (eta, position, token) = response.queue_position()?;
+            queue(api, token, eta, position, handler)
+                .await
+                .map_err(|err| LoginError::Queue(Box::new(err)))
         }
     }
 }
 
-async fn dispatch_2fa_possible(client: &Client, response: LoginResponse) -> Option<LoginToken> {
+async fn dispatch_2fa_possible<H: LoginHandler>(
+    api: &Api<'_>,
+    response: LoginResponse,
+    handler: &mut H,
+) -> Result<LoginToken, LoginError> {
     if response.success.is_partial() {
-        two_factor(&client, response.response_token.unwrap()).await
+        let token = required(&response.response_token, "responseToken")?;
+        two_factor(api, token, handler).await
     } else {
-        dispatch_no_2fa(client, response).await
+        dispatch_no_2fa(api, response, handler).await
     }
 }
 
-async fn two_factor(client: &Client, token: String) -> Option<LoginToken> {
+async fn two_factor<H: LoginHandler>(
+    api: &Api<'_>,
+    token: String,
+    handler: &mut H,
+) -> Result<LoginToken, LoginError> {
     let mut token = token;
     loop {
-        let totp = rprompt::prompt_reply_stdout("2 factor TOTP code: ").expect("Reading 2fa stdin");
-        let totp_request = client
-            .post(LOGIN_URL)
-            .form(&TOTPRequest { totp, token })
-            .build()
-            .expect("Forming request 2fa");
-        match client.execute(totp_request).await {
-            Ok(res) => {
-                let response_result = res.json::<LoginResponse>().await;
-                let response = response_result_none_return!(response_result);
-                if response.success.is_partial() {
-                    println!("Unrecognized TOTP code.");
-                    token = response.response_token.unwrap();
-                } else {
-                    return dispatch_no_2fa(client, response).await;
-                }
-            }
-            Err(err) => {
-                eprintln!(
-                    "An error occurred while executing the 2fa request.\n{}",
-                    err
-                );
-                return None;
-            }
+        let totp = handler.totp_code().ok_or(LoginError::TwoFactorRequired)?;
+        let response = post(api, &TOTPRequest { totp, token }).await?;
+        if response.success.is_partial() {
+            handler.event(LoginEvent::TotpRejected);
+            token = required(&response.response_token, "responseToken")?;
+        } else {
+            return dispatch_no_2fa(api, response, handler).await;
         }
     }
 }
 
-async fn queue(client: &Client, token: String, eta: u32, position: u32) -> Option<LoginToken> {
+async fn queue<H: LoginHandler>(
+    api: &Api<'_>,
+    token: String,
+    eta: u32,
+    position: u32,
+    handler: &mut H,
+) -> Result<LoginToken, LoginError> {
     let mut eta = eta;
     let mut position = position;
     let mut token = token;
     loop {
-        println!("In queue -- Position: {}, ETA: {} seconds.", position, eta);
+        handler.event(LoginEvent::Queued { position, eta });
         async_std::task::sleep(Duration::from_secs(eta as u64)).await;
-        let queue_request = client
-            .post(LOGIN_URL)
-            .form(&QueueToken {
+        let resp = post(
+            api,
+            &QueueToken {
                 queue_token: token.clone(),
-            })
-            .build()
-            .expect("Forming request queue");
-
-        let resp = client.execute(queue_request).await;
-        let resp = if resp.is_ok() {
-            resp.unwrap().json::<LoginResponse>().await
-        } else {
-            Err(resp.unwrap_err())
-        };
-        match resp {
-            Err(err) => {
-                eprintln!("Failed to update position in queue.\n{}", err);
-                return None;
+            },
+        )
+        .await?;
+        match resp.success {
+            LoginResult::Success => return resp.token(),
+            LoginResult::Failure => {
+                return Err(LoginError::BadCredentials {
+                    banner: resp.banner,
+                })
             }
-            Ok(resp) => {
-                if resp.success.is_success() {
-                    return Some(LoginToken {
-                        server: resp.gameserver.unwrap(),
-                        cookie: resp.cookie.unwrap(),
-                    });
-                } else {
-                    eta = u32::from_str(resp.eta.as_ref().unwrap()).expect("parsing eta number");
-                    position = u32::from_str(resp.position.as_ref().unwrap())
-                        .expect("parsing position number");
-                    token = resp.queue_token.as_ref().unwrap().clone();
-                }
+            _ => {
+                let next = resp.queue_position()?;
+                eta = next.0;
+                position = next.1;
+                token = next.2;
             }
         }
     }
@@ -189,6 +190,8 @@ async fn queue(client: &Client, token: String, eta: u32, position: u32) -> Optio
 pub struct LoginToken {
     pub server: String,
     pub cookie: String,
+    /// Message the server attached to the successful login, if any.
+    pub banner: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -237,34 +240,130 @@ enum LoginResult {
     Failure,
 }
 
+impl LoginResponse {
+    fn token(&self) -> Result<LoginToken, LoginError> {
+        Ok(LoginToken {
+            server: required(&self.gameserver, "gameserver")?,
+            cookie: required(&self.cookie, "cookie")?,
+            banner: self.banner.clone(),
+        })
+    }
+
+    /// The eta, position and queue token of a delayed response.
+    fn queue_position(&self) -> Result<(u32, u32, String), LoginError> {
+        Ok((
+            required_number(&self.eta, "eta")?,
+            required_number(&self.position, "position")?,
+            required(&self.queue_token, "queueToken")?,
+        ))
+    }
+}
+
+/// Clones a field the response is expected to carry at this stage.
+fn required(value: &Option<String>, name: &'static str) -> Result<String, LoginError> {
+    value
+        .clone()
+        .ok_or(LoginError::MalformedResponse { field: name })
+}
+
+fn required_number(value: &Option<String>, name: &'static str) -> Result<u32, LoginError> {
+    u32::from_str(&required(value, name)?)
+        .map_err(|_| LoginError::MalformedResponse { field: name })
+}
+
 #[allow(unused)]
 impl LoginResult {
     pub fn is_success(&self) -> bool {
-        if let Self::Success = self {
-            true
-        } else {
-            false
-        }
+        matches!(self, Self::Success)
     }
     pub fn is_delayed(&self) -> bool {
-        if let Self::Delayed = self {
-            true
-        } else {
-            false
-        }
+        matches!(self, Self::Delayed)
     }
     pub fn is_partial(&self) -> bool {
-        if let Self::Partial = self {
-            true
-        } else {
-            false
-        }
+        matches!(self, Self::Partial)
     }
     pub fn is_failure(&self) -> bool {
-        if let Self::Failure = self {
-            true
-        } else {
-            false
+        matches!(self, Self::Failure)
+    }
+}
+
+mod error {
+    use std::fmt::{Debug, Formatter, Result as FmtResult};
+
+    #[derive(Debug)]
+    pub enum LoginError {
+        /// The server rejected the login. The banner usually explains why: a bad password,
+        /// maintenance, a banned account and so on.
+        BadCredentials {
+            banner: Option<String>,
+        },
+        /// The account requires a two factor code, but none was supplied.
+        TwoFactorRequired,
+        /// The account was placed in the login queue, but following it failed.
+        Queue(Box<LoginError>),
+        /// The server response lacked a field, or it held an unexpected value.
+        MalformedResponse {
+            field: &'static str,
+        },
+        /// The server response could not be parsed at all.
+        Parsing(serde_json::Error),
+        /// The login endpoint can't be requested.
+        InvalidUrl(String),
+        Network(reqwest::Error),
+        Keyring(keyring::KeyringError),
+    }
+
+    impl std::error::Error for LoginError {}
+    impl std::fmt::Display for LoginError {
+        fn fmt(&self, f: &mut Formatter) -> FmtResult {
+            match self {
+                Self::BadCredentials {
+                    banner: Some(banner),
+                } => {
+                    write!(f, "The login was rejected: {}", banner)
+                }
+                Self::BadCredentials { banner: None } => {
+                    write!(f, "The username or password was rejected")
+                }
+                Self::TwoFactorRequired => write!(f, "A two factor code is required"),
+                Self::Queue(inner) => write!(f, "Failed to move through the queue: {}", inner),
+                Self::MalformedResponse { field } => write!(
+                    f,
+                    "The login response had a missing or invalid `{}` field",
+                    field
+                ),
+                Self::Parsing(inner) => write!(f, "The login response was malformed: {}", inner),
+                Self::InvalidUrl(reason) => write!(f, "Invalid login URL: {}", reason),
+                Self::Network(inner) => {
+                    write!(
+                        f,
+                        "Error occurred while contacting the login server: {}",
+                        inner
+                    )
+                }
+                Self::Keyring(inner) => write!(f, "A keyring error occurred: {}", inner),
+            }
+        }
+    }
+
+    impl From<reqwest::Error> for LoginError {
+        fn from(err: reqwest::Error) -> Self {
+            if err.is_builder() {
+                return Self::InvalidUrl(err.to_string());
+            }
+            Self::Network(err)
+        }
+    }
+
+    impl From<serde_json::Error> for LoginError {
+        fn from(err: serde_json::Error) -> Self {
+            Self::Parsing(err)
+        }
+    }
+
+    impl From<keyring::KeyringError> for LoginError {
+        fn from(err: keyring::KeyringError) -> Self {
+            Self::Keyring(err)
         }
     }
 }
@@ -1,77 +1,138 @@
 #![deny(unreachable_code, unreachable_patterns, unused_assignments, unused_must_use, unused_extern_crates)]
 #![warn(unused_qualifications, unused_import_braces)]
 
-use tokio::io::{AsyncBufReadExt, BufReader};
+use config::Config;
+use opt::Command;
+use ttr_launcher_oxide::update::{self, UpdateMode};
 
-mod launch;
-mod login;
+mod accounts;
+mod config;
 mod opt;
-mod update;
+mod play;
+mod terminal;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Update
-
     let opts = opt::get_options();
-    if !opts.no_update {
-        if let Err(err) = update::update(&opts).await {
-            eprintln!("Failed to update!\n{}", err);
+    let config_path = match opts.config.clone().or_else(config::default_path) {
+        Some(path) => path,
+        None => {
+            eprintln!("Unsupported OS. You will have to use the --config option.");
             return Ok(());
         }
-    }
-
-    // Get Username / pass
-    let (username, password) = if opts.pass_stdin {
-        // If the password is passed from stdin, read it
-        let username = opts.username.as_ref().unwrap().as_str();
-        let mut bufreader = BufReader::new(tokio::io::stdin());
-        let mut password = String::new();
-        bufreader.read_line(&mut password).await?;
-        let pass = password.trim();
-        (username.to_string(), Some(pass.to_string()))
-    } else {
-        let username = if opts.username.is_none() {
-            let u = rprompt::prompt_reply_stdout("Username: ")?;
-            u.trim().to_string()
-        } else {
-            opts.username.as_ref().unwrap().clone()
-        };
-        println!("Logging in on {}", &username);
-        // Retrieve password from keyring or tty
-        let pass: Option<String> = if opts.keyring && !opts.reset_keyring {
-            None
-        } else {
-            let pass = rpassword::read_password_from_tty(Some("Password: "))?;
-            Some(pass)
-        };
-
-        (username.to_string(), pass)
     };
-
-    // If user requested keyring reset, do that
-    if opts.reset_keyring {
-        login::reset_keyring(username.as_str());
+    let mut config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Failed to load {}\n{}", config_path.display(), err);
+            std::process::exit(1);
+        }
+    };
+    if let Err(err) = opts.check_endpoints(&config) {
+        eprintln!("{}", err);
+        std::process::exit(1);
     }
 
-    // Login
-    let save_password = password.is_some() && opts.keyring;
-    match login::login(username, password, save_password).await {
-        Some(login_cookie) => {
-            println!("Logged in successfully! {}", &login_cookie.server);
-            // Launch
-            if !opts.manual {
-                launch::launch(&opts, login_cookie).await;
+    let code = match opts.command {
+        None => {
+            let play = &opts.play;
+            play::play(
+                &opts,
+                &play.login,
+                !play.no_update,
+                play.force,
+                play.manual,
+                &config,
+            )
+            .await?
+        }
+        Some(Command::Play(ref play)) => {
+            play::play(
+                &opts,
+                &play.login,
+                !play.no_update,
+                play.force,
+                play.manual,
+                &config,
+            )
+            .await?
+        }
+        Some(Command::Login(ref login)) => {
+            play::play(&opts, login, false, false, true, &config).await?
+        }
+        Some(Command::Launch(ref login)) => {
+            play::play(&opts, login, false, false, false, &config).await?
+        }
+        Some(Command::Update {
+            dry_run: true,
+            json,
+        }) => {
+            let update_config = opts.update_config(&opts.install_dir, &config);
+            match update::plan(&update_config).await {
+                Ok(plan) => {
+                    terminal::print_plan(&plan, json);
+                    0
+                }
+                Err(err) => {
+                    eprintln!("Failed to plan the update!\n{}", err);
+                    1
+                }
+            }
+        }
+        Some(Command::Update { .. }) => {
+            let update_config = opts.update_config(&opts.install_dir, &config);
+            match terminal::update(&update_config, opts.quiet).await {
+                Ok(_) => 0,
+                Err(err) => {
+                    eprintln!("Failed to update!\n{}", err);
+                    1
+                }
+            }
+        }
+        Some(Command::Repair { force }) => {
+            let mut update_config = opts.update_config(&opts.install_dir, &config);
+            update_config.mode = if force {
+                UpdateMode::Reinstall
             } else {
-                println!(
-                    "TTR_GAMESERVER={}\nTTR_PLAYCOOKIE={}",
-                    login_cookie.server, login_cookie.cookie
-                );
+                UpdateMode::Repair
+            };
+            match terminal::update(&update_config, opts.quiet).await {
+                Ok(_) => 0,
+                Err(err) => {
+                    eprintln!("Failed to repair!\n{}", err);
+                    1
+                }
             }
         }
-        None => {
-            println!("Failed to log in.");
+        Some(Command::Verify) => {
+            let update_config = opts.update_config(&opts.install_dir, &config);
+            match update::verify(&update_config).await {
+                Ok(report) => terminal::verify_report(&report),
+                Err(err) => {
+                    eprintln!("Failed to verify!\n{}", err);
+                    1
+                }
+            }
+        }
+        Some(Command::Accounts(command)) => {
+            match accounts::accounts(command, &mut config, &config_path) {
+                Ok(()) => 0,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    1
+                }
+            }
         }
+        Some(Command::Keyring(command)) => match accounts::keyring(command, &config) {
+            Ok(()) => 0,
+            Err(err) => {
+                eprintln!("{}", err);
+                1
+            }
+        },
+    };
+    if code != 0 {
+        std::process::exit(code);
     }
-
     Ok(())
 }
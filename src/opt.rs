@@ -1,25 +1,232 @@
-use std::path::PathBuf;
+use crate::config::Config;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use structopt::StructOpt;
+use ttr_launcher_oxide::login::{self, DEFAULT_LOGIN_URL};
+use ttr_launcher_oxide::update::UpdateConfig;
 
 #[derive(StructOpt)]
 #[structopt()]
 pub struct Options {
     /// Sets the installation directory for Toontown Rewritten
-    #[structopt(long, env, parse(from_os_str), default_value = unsafe { INSTALL_DIR.as_str() })]
+    #[structopt(long, env, global = true, parse(from_os_str), default_value = unsafe { &*std::ptr::addr_of!(INSTALL_DIR) }.as_str())]
     pub install_dir: PathBuf,
 
+    /// Sets the config file holding account profiles
+    #[structopt(long, env = "TTR_CONFIG", global = true, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Hides update progress, for use in scripts.
+    #[structopt(long, short, global = true)]
+    pub quiet: bool,
+
+    /// How many files to update at once. Defaults to 4.
+    #[structopt(long, short, global = true)]
+    pub jobs: Option<usize>,
+
+    /// Hashes every game file, even ones whose size and modification time haven't changed since
+    /// the last update.
+    #[structopt(long, global = true)]
+    pub full_verify: bool,
+
+    /// Deletes game files that are no longer part of the game, instead of only listing them.
+    /// Never touches screenshots, logs or settings.
+    #[structopt(long, global = true)]
+    pub prune: bool,
+
+    /// How many times to retry a failed download. Defaults to 3.
+    #[structopt(long, global = true)]
+    pub retries: Option<u32>,
+
+    /// Milliseconds to wait before the first retry. The wait doubles after each one.
+    #[structopt(long, global = true)]
+    pub retry_delay: Option<u64>,
+
+    /// Longest wait between two retries, in milliseconds. Defaults to 30000.
+    #[structopt(long, global = true)]
+    pub retry_max_delay: Option<u64>,
+
+    /// Waits exactly the retry delay, instead of a random part of it.
+    #[structopt(long, global = true)]
+    pub no_retry_jitter: bool,
+
+    /// HTTP status to retry. Repeat for several. Defaults to 408, 429, 500, 502, 503 and 504.
+    #[structopt(
+        long = "retry-status",
+        global = true,
+        number_of_values = 1,
+        use_delimiter = true
+    )]
+    pub retry_statuses: Vec<u16>,
+
+    /// Doesn't retry requests that timed out.
+    #[structopt(long, global = true)]
+    pub no_retry_timeouts: bool,
+
+    /// Doesn't retry requests that got no answer, such as refused or dropped connections.
+    #[structopt(long, global = true)]
+    pub no_retry_connection_errors: bool,
+
+    /// Sets the login endpoint, overriding the config file
+    #[structopt(long, env = "TTR_LOGIN_URL", global = true)]
+    pub login_url: Option<String>,
+
+    /// Sets where the patch manifest is fetched from, overriding the config file
+    #[structopt(long, env = "TTR_MANIFEST_URL", global = true)]
+    pub manifest_url: Option<String>,
+
+    /// Downloads from a CDN mirror instead of the official CDN. Repeat to fall back on several
+    /// mirrors in order. Overrides the mirrors in the config file.
+    #[structopt(
+        long = "mirror",
+        env = "TTR_MIRRORS",
+        global = true,
+        number_of_values = 1,
+        use_delimiter = true
+    )]
+    pub mirrors: Vec<String>,
+
+    /// Shares downloads through this directory, such as a network share used by several
+    /// machines. Files found there aren't downloaded again, and new downloads are added to it.
+    #[structopt(long, env = "TTR_CACHE_DIR", global = true, parse(from_os_str))]
+    pub cache_dir: Option<PathBuf>,
+
+    // Options for `play`, which runs when no subcommand is given
+    #[structopt(flatten)]
+    pub play: PlayOptions,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Options {
+    /// Update settings for `install_dir`, as set on the command line or in the config file.
+    pub fn update_config(&self, install_dir: &Path, file: &Config) -> UpdateConfig {
+        let mut config = UpdateConfig::new(install_dir);
+        if let Some(url) = self
+            .manifest_url
+            .as_ref()
+            .or(file.endpoints.manifest_url.as_ref())
+        {
+            config.manifest_url = url.clone();
+        }
+        if !self.mirrors.is_empty() {
+            config.mirrors = self.mirrors.clone();
+        } else if !file.endpoints.mirrors.is_empty() {
+            config.mirrors = file.endpoints.mirrors.clone();
+        }
+        if let Some(jobs) = self.jobs {
+            config.jobs = jobs;
+        }
+        config.cache_dir = self.cache_dir.clone();
+        config.full_verify = self.full_verify;
+        config.prune = self.prune;
+        if let Some(retries) = self.retries {
+            config.retry.attempts = retries.saturating_add(1);
+        }
+        if let Some(delay) = self.retry_delay {
+            config.retry.base_delay = Duration::from_millis(delay);
+        }
+        if let Some(delay) = self.retry_max_delay {
+            config.retry.max_delay = Duration::from_millis(delay);
+        }
+        if !self.retry_statuses.is_empty() {
+            config.retry.statuses = self.retry_statuses.clone();
+        }
+        config.retry.jitter = !self.no_retry_jitter;
+        config.retry.timeouts = !self.no_retry_timeouts;
+        config.retry.connection_errors = !self.no_retry_connection_errors;
+        config
+    }
+
+    /// Checks every endpoint set on the command line or in the config file, so a typo fails
+    /// before any prompts rather than halfway through.
+    pub fn check_endpoints(&self, file: &Config) -> Result<(), Box<dyn std::error::Error>> {
+        self.update_config(&self.install_dir, file).check_urls()?;
+        login::check_url(self.login_url(file))?;
+        Ok(())
+    }
+
+    /// The login endpoint, as set on the command line or in the config file.
+    pub fn login_url<'a>(&'a self, file: &'a Config) -> &'a str {
+        self.login_url
+            .as_deref()
+            .or(file.endpoints.login_url.as_deref())
+            .unwrap_or(DEFAULT_LOGIN_URL)
+    }
+}
+
+#[derive(StructOpt)]
+pub enum Command {
+    /// Updates the game, logs in and launches it. This is the default.
+    Play(PlayOptions),
+    /// Only updates the game.
+    Update {
+        /// Prints what the update would do and how much it would download, without changing
+        /// anything
+        #[structopt(long)]
+        dry_run: bool,
+        /// Prints the dry run as JSON
+        #[structopt(long, requires("dry-run"))]
+        json: bool,
+    },
+    /// Downloads every file that doesn't match the manifest again, without patching.
+    Repair {
+        /// Downloads every file again, even ones that match
+        #[structopt(long)]
+        force: bool,
+    },
+    /// Logs in and prints the game server and cookie for manual launching.
+    Login(LoginOptions),
+    /// Logs in and launches the game without updating it.
+    Launch(LoginOptions),
+    /// Checks the installation against the manifest without changing anything. Exits with 2 if
+    /// any file is modified or missing.
+    Verify,
+    /// Manages the account profiles in the config file.
+    Accounts(AccountsCommand),
+    /// Manages passwords held in the system keyring.
+    Keyring(KeyringCommand),
+}
+
+#[derive(StructOpt)]
+pub struct PlayOptions {
     /// Disables updating, will try to login without doing so.
     #[structopt(long, short = "d")]
     pub no_update: bool,
 
-    /// Pass passwords via stdin. If this is set, you must use --username to specify a username.
+    /// Dumps the cookie and game server to stdout for manual launching
+    #[structopt(long)]
+    pub manual: bool,
+
+    /// Launches even if some files failed to update.
+    #[structopt(long)]
+    pub force: bool,
+
+    #[structopt(flatten)]
+    pub login: LoginOptions,
+}
+
+#[derive(StructOpt)]
+pub struct LoginOptions {
+    /// Pass passwords via stdin. If this is set, you must use --username or --profile to specify a
+    /// username.
     #[structopt(long, short = "s")]
     pub pass_stdin: bool,
 
     /// Specifies a username
-    #[structopt(long, short, required_if("pass_stdin", "true"))]
+    #[structopt(long, short, conflicts_with("profile"))]
     pub username: Option<String>,
 
+    /// Logs in with a profile from the config file, which supplies the username and keyring
+    /// settings. Repeat to launch several accounts at once.
+    #[structopt(long, short, number_of_values = 1)]
+    pub profile: Vec<String>,
+
+    /// Launches every profile in the config file at once.
+    #[structopt(long, conflicts_with_all(&["profile", "username"]))]
+    pub all_profiles: bool,
+
     /// If enabled, the system keyring will be used to save and remember passwords.
     #[structopt(long, short)]
     pub keyring: bool,
@@ -27,10 +234,43 @@ pub struct Options {
     /// Forgets any password held in the keyring.
     #[structopt(long)]
     pub reset_keyring: bool,
+}
 
-    /// Dumps the cookie and game server to stdout for manual launching
-    #[structopt(long)]
-    pub manual: bool,
+#[derive(StructOpt)]
+pub enum AccountsCommand {
+    /// Adds a profile, replacing any existing profile with the same name.
+    Add {
+        /// Name of the profile
+        name: String,
+        /// Username of the account
+        username: String,
+        /// Remember the password in the system keyring
+        #[structopt(long, short)]
+        keyring: bool,
+        /// Installation directory to use for this account instead of the default
+        #[structopt(name = "profile-install-dir", long, parse(from_os_str))]
+        install_dir: Option<PathBuf>,
+    },
+    /// Removes a profile.
+    Remove {
+        /// Name of the profile
+        name: String,
+    },
+    /// Lists the profiles.
+    List,
+}
+
+#[derive(StructOpt)]
+pub enum KeyringCommand {
+    /// Forgets the password held for an account.
+    Forget {
+        /// Username of the account
+        #[structopt(required_unless("profile"))]
+        username: Option<String>,
+        /// Profile whose account should be forgotten
+        #[structopt(long, short, conflicts_with("username"))]
+        profile: Option<String>,
+    },
 }
 
 static mut INSTALL_DIR: String = String::new();
@@ -0,0 +1,222 @@
+use crate::config::{Config, ConfigError};
+use crate::opt::{LoginOptions, Options};
+use crate::terminal::{self, login_exit_code, TerminalLogin};
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use ttr_launcher_oxide::launch::LaunchExtras;
+use ttr_launcher_oxide::login::LoginToken;
+use ttr_launcher_oxide::{launch, login};
+
+/// One account to log in and launch.
+struct Account {
+    /// The profile name, if the account came from the config file.
+    label: Option<String>,
+    username: Option<String>,
+    keyring: bool,
+    install_dir: PathBuf,
+    extras: LaunchExtras,
+}
+
+/// An account that is ready to log in.
+struct Ready {
+    account: Account,
+    username: String,
+    password: Option<String>,
+}
+
+/// Resolves the accounts requested on the command line. Without any profiles, this is the
+/// single account described by `--username` and `--keyring`.
+fn accounts(
+    global: &Options,
+    opts: &LoginOptions,
+    config: &Config,
+) -> Result<Vec<Account>, ConfigError> {
+    let names: Vec<&String> = if opts.all_profiles {
+        if config.profiles.is_empty() {
+            return Err(ConfigError::NoProfiles);
+        }
+        config.profiles.keys().collect()
+    } else {
+        opts.profile.iter().collect()
+    };
+    if names.is_empty() {
+        return Ok(vec![Account {
+            label: None,
+            username: opts.username.clone(),
+            keyring: opts.keyring,
+            install_dir: global.install_dir.clone(),
+            extras: LaunchExtras::default(),
+        }]);
+    }
+    names
+        .into_iter()
+        .map(|name| {
+            let profile = config.profile(name)?;
+            Ok(Account {
+                label: Some(name.clone()),
+                username: Some(profile.username.clone()),
+                keyring: opts.keyring || profile.keyring,
+                install_dir: profile
+                    .install_dir
+                    .clone()
+                    .unwrap_or_else(|| global.install_dir.clone()),
+                extras: profile.launch.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Logs in every requested account and launches it, or prints its token when `manual` is set.
+/// With `update`, each installation is updated first, and a failed update stops the launch
+/// unless `force` is set. Returns the process exit code.
+pub async fn play(
+    global: &Options,
+    opts: &LoginOptions,
+    update: bool,
+    force: bool,
+    manual: bool,
+    config: &Config,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let accounts = match accounts(global, opts, config) {
+        Ok(accounts) => accounts,
+        Err(err) => {
+            eprintln!("{}", err);
+            return Ok(1);
+        }
+    };
+
+    // Update every installation once, even when several accounts share it
+    if update {
+        let mut install_dirs: Vec<&PathBuf> = accounts.iter().map(|a| &a.install_dir).collect();
+        install_dirs.sort();
+        install_dirs.dedup();
+        for install_dir in install_dirs {
+            let update_config = global.update_config(install_dir, config);
+            match terminal::update(&update_config, global.quiet).await {
+                Ok(_) => {}
+                Err(err) if force => eprintln!("Failed to update, launching anyway!\n{}", err),
+                Err(err) => {
+                    eprintln!("Failed to update!\n{}\nUse --force to launch anyway.", err);
+                    return Ok(1);
+                }
+            }
+        }
+    }
+
+    // Gather credentials up front, one account at a time, so prompts don't interleave
+    let mut stdin = BufReader::new(tokio::io::stdin());
+    let mut ready = Vec::with_capacity(accounts.len());
+    for account in accounts {
+        let prefix = TerminalLogin::new(account.label.as_deref())
+            .prefix()
+            .to_string();
+        let username = match (&account.username, opts.pass_stdin) {
+            (Some(username), _) => username.clone(),
+            (None, true) => {
+                eprintln!("--pass-stdin requires --username or --profile");
+                return Ok(1);
+            }
+            (None, false) => {
+                let u = rprompt::prompt_reply_stdout("Username: ")?;
+                u.trim().to_string()
+            }
+        };
+        if !opts.pass_stdin {
+            println!("{}Logging in on {}", prefix, &username);
+        }
+
+        // If user requested keyring reset, do that
+        if opts.reset_keyring {
+            if let Err(err) = login::reset_keyring(username.as_str()) {
+                eprintln!("{}Failed to delete password!\n{}", prefix, err);
+            }
+        }
+
+        // Retrieve password from stdin, keyring or tty
+        let password = if opts.pass_stdin {
+            let mut password = String::new();
+            stdin.read_line(&mut password).await?;
+            Some(password.trim().to_string())
+        } else if account.keyring && !opts.reset_keyring {
+            None
+        } else {
+            let prompt = format!("{}Password: ", prefix);
+            Some(rpassword::read_password_from_tty(Some(&prompt))?)
+        };
+        ready.push(Ready {
+            account,
+            username,
+            password,
+        });
+    }
+
+    // Log in and launch all accounts concurrently
+    let url = global.login_url(config);
+    let sessions = ready
+        .into_iter()
+        .map(|ready| tokio::spawn(session(url.to_string(), manual, ready)));
+    let mut exit_code = 0;
+    for code in futures::future::join_all(sessions).await {
+        let code = code.unwrap_or_else(|err| {
+            eprintln!("A login crashed! This is a bug.\n{}", err);
+            1
+        });
+        if exit_code == 0 {
+            exit_code = code;
+        }
+    }
+    Ok(exit_code)
+}
+
+/// Logs in a single account at `url` and launches it, or prints its token when `manual` is set.
+async fn session(url: String, manual: bool, ready: Ready) -> i32 {
+    let Ready {
+        account,
+        username,
+        password,
+    } = ready;
+    let mut handler = TerminalLogin::new(account.label.as_deref());
+    let save_password = password.is_some() && account.keyring;
+    match login::login(&url, &username, password, save_password, &mut handler).await {
+        Ok(login_cookie) => {
+            let prefix = handler.prefix();
+            println!("{}Logged in successfully! {}", prefix, &login_cookie.server);
+            if let Some(banner) = &login_cookie.banner {
+                println!("{}{}", prefix, banner);
+            }
+            // Launch
+            if !manual {
+                run(prefix, &account, login_cookie).await;
+            } else {
+                if let Some(label) = &account.label {
+                    println!("TTR_PROFILE={}", label);
+                }
+                println!(
+                    "TTR_GAMESERVER={}\nTTR_PLAYCOOKIE={}",
+                    login_cookie.server, login_cookie.cookie
+                );
+                if let Some(banner) = &login_cookie.banner {
+                    // Kept to a single line so the output stays one variable per line
+                    println!("TTR_BANNER={}", banner.replace('\n', " "));
+                }
+            }
+            0
+        }
+        Err(err) => {
+            eprintln!("{}Failed to log in.\n{}", handler.prefix(), err);
+            login_exit_code(&err)
+        }
+    }
+}
+
+async fn run(prefix: &str, account: &Account, token: LoginToken) {
+    match launch::launch(&account.install_dir, token, &account.extras).await {
+        Ok(status) if status.success() => println!("{}TTREngine exited normally", prefix),
+        Ok(status) => eprintln!(
+            "{}TTREngine executed abnormally! Exit code: {:?}",
+            prefix,
+            status.code()
+        ),
+        Err(err) => eprintln!("{}{}", prefix, err),
+    }
+}
@@ -0,0 +1,268 @@
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use ttr_launcher_oxide::login::{LoginError, LoginEvent, LoginHandler};
+use ttr_launcher_oxide::update::{
+    self, FileOutcome, FileStatus, PlannedAction, UpdateConfig, UpdateError, UpdateEvent,
+    UpdatePlan, UpdateSummary, VerifyReport,
+};
+
+/// Reports login progress on the terminal and prompts for two factor codes.
+pub struct TerminalLogin {
+    /// Prefixed to every line, to tell accounts apart when several log in at once.
+    prefix: String,
+}
+
+impl TerminalLogin {
+    pub fn new(label: Option<&str>) -> Self {
+        Self {
+            prefix: label.map(|l| format!("[{}] ", l)).unwrap_or_default(),
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+}
+
+impl LoginHandler for TerminalLogin {
+    fn event(&mut self, event: LoginEvent) {
+        match event {
+            LoginEvent::Queued { position, eta } => println!(
+                "{}In queue -- Position: {}, ETA: {} seconds.",
+                self.prefix, position, eta
+            ),
+            LoginEvent::TotpRejected => println!("{}Unrecognized TOTP code.", self.prefix),
+            LoginEvent::PasswordNotSaved(err) => eprintln!(
+                "{}Failed to save password in keyring.\n{}",
+                self.prefix, err
+            ),
+        }
+    }
+
+    fn totp_code(&mut self) -> Option<String> {
+        // Accounts log in on separate tasks: keep the prompt off the runtime's other work and
+        // ask for one code at a time, so concurrent prompts don't interleave.
+        static PROMPT: Mutex<()> = Mutex::new(());
+        tokio::task::block_in_place(|| {
+            let _prompt = PROMPT.lock().unwrap_or_else(|err| err.into_inner());
+            rprompt::prompt_reply_stdout(&format!("{}2 factor TOTP code: ", self.prefix)).ok()
+        })
+    }
+}
+
+/// Runs an update, showing its progress and a summary unless `quiet` is set.
+pub async fn update(config: &UpdateConfig, quiet: bool) -> Result<UpdateSummary, UpdateError> {
+    if quiet {
+        return update::update(config, |_| {}).await;
+    }
+    let progress = Arc::new(UpdateProgress::new());
+    let reporter = progress.clone();
+    let result = update::update(config, move |event| reporter.event(event)).await;
+    progress.finish();
+    if let Ok(summary) = &result {
+        println!(
+            "{} patched, {} downloaded, {} already up to date.",
+            summary.count(|o| matches!(o, FileOutcome::Patched)),
+            summary.count(|o| matches!(o, FileOutcome::Downloaded)),
+            summary.count(|o| matches!(o, FileOutcome::UpToDate)),
+        );
+        print_obsolete(summary, config.prune);
+    }
+    result
+}
+
+/// Lists the files that are no longer part of the game, and whether they were deleted.
+fn print_obsolete(summary: &UpdateSummary, pruned: bool) {
+    if !pruned {
+        if !summary.obsolete.is_empty() {
+            println!(
+                "{} files are no longer used. Use --prune to remove them:",
+                summary.obsolete.len()
+            );
+            for file in &summary.obsolete {
+                println!("  {}", file);
+            }
+        }
+        return;
+    }
+    let removed: Vec<&String> = summary
+        .obsolete
+        .iter()
+        .filter(|file| summary.prune_failures.iter().all(|(f, _)| f != *file))
+        .collect();
+    if !removed.is_empty() {
+        println!("Removed {} files that are no longer used:", removed.len());
+        for file in removed {
+            println!("  {}", file);
+        }
+    }
+    for (file, err) in &summary.prune_failures {
+        eprintln!("Failed to remove {}: {}", file, err);
+    }
+}
+
+/// Progress bars for an update: one for the whole run, plus one per running download.
+struct UpdateProgress {
+    multi: MultiProgress,
+    overall: ProgressBar,
+    downloads: Mutex<HashMap<String, ProgressBar>>,
+}
+
+impl UpdateProgress {
+    fn new() -> Self {
+        let multi = MultiProgress::new();
+        let overall = multi.add(ProgressBar::new_spinner());
+        overall.set_message("Fetching manifest");
+        overall.enable_steady_tick(Duration::from_millis(100));
+        Self {
+            multi,
+            overall,
+            downloads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn event(&self, event: UpdateEvent) {
+        match event {
+            UpdateEvent::ManifestFetched { files } => {
+                self.overall.disable_steady_tick();
+                self.overall.set_style(
+                    ProgressStyle::with_template("Updating [{bar:40}] {pos}/{len} files")
+                        .unwrap()
+                        .progress_chars("=> "),
+                );
+                self.overall.set_length(files as u64);
+            }
+            UpdateEvent::UpToDate(file) => self.file_done(&file, None),
+            UpdateEvent::Downloaded(file) => self.file_done(&file, Some("Downloaded")),
+            UpdateEvent::Patched(file) => self.file_done(&file, Some("Patched")),
+            UpdateEvent::Failed(file) => self.file_done(&file, Some("Failed to update")),
+            UpdateEvent::DownloadStarted { file, total } => {
+                let bar = match total {
+                    Some(total) => ProgressBar::new(total).with_style(
+                        ProgressStyle::with_template(
+                            "{msg:30!} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec}",
+                        )
+                        .unwrap()
+                        .progress_chars("=> "),
+                    ),
+                    None => ProgressBar::new_spinner().with_style(
+                        ProgressStyle::with_template("{msg:30!} {spinner} {bytes}").unwrap(),
+                    ),
+                };
+                let bar = self.multi.add(bar.with_message(file.clone()));
+                let mut downloads = self.downloads.lock().unwrap();
+                if let Some(old) = downloads.insert(file, bar) {
+                    old.finish_and_clear();
+                }
+            }
+            UpdateEvent::DownloadProgress { file, received } => {
+                if let Some(bar) = self.downloads.lock().unwrap().get(&file) {
+                    bar.set_position(received);
+                }
+            }
+        }
+    }
+
+    fn file_done(&self, file: &str, message: Option<&str>) {
+        if let Some(bar) = self.downloads.lock().unwrap().remove(file) {
+            bar.finish_and_clear();
+            self.multi.remove(&bar);
+        }
+        if let Some(message) = message {
+            self.overall.println(format!("{} {}", message, file));
+        }
+        self.overall.inc(1);
+    }
+
+    fn finish(&self) {
+        for (_, bar) in self.downloads.lock().unwrap().drain() {
+            bar.finish_and_clear();
+        }
+        self.overall.finish_and_clear();
+    }
+}
+
+/// Prints what an update would do to each file that isn't up to date, either for people or as
+/// JSON.
+pub fn print_plan(plan: &UpdatePlan, json: bool) {
+    if json {
+        let json = serde_json::to_string_pretty(plan).expect("Serializing the plan");
+        println!("{}", json);
+        return;
+    }
+    let (mut current, mut patches, mut downloads) = (0, 0, 0);
+    for planned in &plan.files {
+        let size = match planned.download_size {
+            Some(size) => HumanBytes(size).to_string(),
+            None => "unknown size".to_string(),
+        };
+        match &planned.action {
+            PlannedAction::UpToDate => current += 1,
+            PlannedAction::Patch { chain } if chain.len() > 1 => {
+                patches += 1;
+                println!(
+                    "Patch {} from {} in {} steps ({})",
+                    planned.file,
+                    chain[0],
+                    chain.len(),
+                    size
+                );
+            }
+            PlannedAction::Patch { chain } => {
+                patches += 1;
+                println!("Patch {} from {} ({})", planned.file, chain[0], size);
+            }
+            PlannedAction::Download => {
+                downloads += 1;
+                println!("Download {} ({})", planned.file, size);
+            }
+            PlannedAction::Missing => {
+                downloads += 1;
+                println!("Download {}, which is missing ({})", planned.file, size);
+            }
+        }
+    }
+    println!(
+        "{} up to date, {} to patch, {} to download. About {} to download in total.",
+        current,
+        patches,
+        downloads,
+        HumanBytes(plan.download_size()),
+    );
+}
+
+/// Prints the files that don't match the manifest, followed by a count of each status.
+/// Returns the exit code: 0 if the installation is intact, 2 if not.
+pub fn verify_report(report: &VerifyReport) -> i32 {
+    for file in report.with_status(FileStatus::Modified) {
+        println!("Modified: {}", file);
+    }
+    for file in report.with_status(FileStatus::Missing) {
+        println!("Missing: {}", file);
+    }
+    println!(
+        "{} up to date, {} modified, {} missing.",
+        report.with_status(FileStatus::UpToDate).count(),
+        report.with_status(FileStatus::Modified).count(),
+        report.with_status(FileStatus::Missing).count(),
+    );
+    if report.is_intact() {
+        0
+    } else {
+        2
+    }
+}
+
+/// Exit code for a failed login, so scripts can tell the failures apart.
+pub fn login_exit_code(err: &LoginError) -> i32 {
+    match err {
+        LoginError::BadCredentials { .. } => 2,
+        LoginError::TwoFactorRequired => 3,
+        LoginError::Queue(_) => 4,
+        LoginError::MalformedResponse { .. } | LoginError::Parsing(_) => 5,
+        LoginError::Network(_) | LoginError::InvalidUrl(_) => 6,
+        LoginError::Keyring(_) => 7,
+    }
+}
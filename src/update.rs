@@ -1,50 +1,568 @@
-use crate::opt::Options;
-
 use crate::update::manifest::{FileObject, PatchObject};
+use download::Download;
 pub use error::*;
-use futures::Future;
+use futures::{Stream, StreamExt, TryStreamExt};
 use manifest::Manifest;
-use sha::sha1::Sha1;
-use sha::utils::{Digest, DigestExt};
+use memmap2::Mmap;
+use reqwest::header::{HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+pub use retry::{RetryPolicy, RETRIABLE_STATUSES};
+use serde::Serialize;
+use state::{CachedManifest, FileRecord, State};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::fs::DirBuilder;
-use std::fs::{self, File};
-use std::io::{Read, Write};
-use std::path::{Path, PathBuf};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+mod download;
+mod retry;
+mod state;
+mod stream;
 
-const MANIFEST_URL: &'static str = "https://cdn.toontownrewritten.com/content/patchmanifest.txt";
-const CDN_BASE_URL: &'static str = "https://download.toontownrewritten.com/patches/";
+/// The official manifest, used unless another is configured.
+pub const DEFAULT_MANIFEST_URL: &str =
+    "https://cdn.toontownrewritten.com/content/patchmanifest.txt";
+/// The official CDN, used unless other mirrors are configured.
+pub const DEFAULT_CDN_URL: &str = "https://download.toontownrewritten.com/patches/";
 
 #[cfg(target_os = "linux")]
-const PLATFORM_KEY: &'static str = "linux2";
+const PLATFORM_KEY: &str = "linux2";
 #[cfg(target_os = "macos")]
-const PLATFORM_KEY: &'static str = "darwin";
+const PLATFORM_KEY: &str = "darwin";
 #[cfg(all(windows, target_arch = "x86_64"))]
-const PLATFORM_KEY: &'static str = "win64";
+const PLATFORM_KEY: &str = "win64";
 #[cfg(all(windows, target_arch = "x86"))]
-const PLATFORM_KEY: &'static str = "win32";
+const PLATFORM_KEY: &str = "win32";
+
+/// Name of the staging directory inside the installation directory.
+const STAGING_DIR: &str = ".staging";
+
+/// How many files are updated at once unless configured otherwise.
+pub const DEFAULT_JOBS: usize = 4;
+
+/// How [`update`] brings files up to date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateMode {
+    /// Patches files where possible and downloads the rest in full.
+    Normal,
+    /// Downloads every file that doesn't match in full, without patching. Recovers files that
+    /// a bad patch left broken.
+    Repair,
+    /// Downloads every file in full, even ones that already match.
+    Reinstall,
+}
+
+/// Settings for an update run.
+#[derive(Debug, Clone)]
+pub struct UpdateConfig {
+    /// The installation directory for Toontown Rewritten
+    pub install_dir: PathBuf,
+    /// How many files are checked and downloaded at once
+    pub jobs: usize,
+    /// How requests to the CDN are retried
+    pub retry: RetryPolicy,
+    /// Where the manifest is fetched from
+    pub manifest_url: String,
+    /// Base URLs of the CDN mirrors, tried in order until one of them has the file
+    pub mirrors: Vec<String>,
+    /// How files are brought up to date
+    pub mode: UpdateMode,
+    /// Hashes every file, instead of trusting that files whose size and modification time are
+    /// unchanged since the last update still match
+    pub full_verify: bool,
+    /// Deletes files that an earlier update installed but the manifest no longer lists
+    pub prune: bool,
+    /// A directory of compressed files and patches shared between installations. Files are
+    /// taken from it when they match the manifest, and new downloads are added to it.
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl UpdateConfig {
+    pub fn new<P: Into<PathBuf>>(install_dir: P) -> Self {
+        Self {
+            install_dir: install_dir.into(),
+            jobs: DEFAULT_JOBS,
+            retry: RetryPolicy::default(),
+            manifest_url: DEFAULT_MANIFEST_URL.to_string(),
+            mirrors: vec![DEFAULT_CDN_URL.to_string()],
+            cache_dir: None,
+            mode: UpdateMode::Normal,
+            full_verify: false,
+            prune: false,
+        }
+    }
+
+    /// Checks that the manifest and every mirror have a URL that can be requested.
+    pub fn check_urls(&self) -> Result<(), UpdateError> {
+        let urls = std::iter::once(&self.manifest_url).chain(&self.mirrors);
+        for url in urls {
+            crate::check_url(url).map_err(UpdateError::InvalidUrl)?;
+        }
+        Ok(())
+    }
+
+    /// Where compressed downloads are kept until they are complete, so that an interrupted
+    /// download can be resumed on the next run.
+    pub fn staging_dir(&self) -> PathBuf {
+        self.install_dir.join(STAGING_DIR)
+    }
+}
+
+/// Progress reported by [`update`]. File names are relative to the installation directory.
+#[derive(Debug, Clone)]
+pub enum UpdateEvent {
+    /// The manifest was fetched and lists `files` files for this platform.
+    ManifestFetched { files: usize },
+    /// The file already matched the manifest.
+    UpToDate(String),
+    /// The file was downloaded in full.
+    Downloaded(String),
+    /// The file was brought up to date with a patch.
+    Patched(String),
+    /// A download for the file started. `total` is the size in bytes, if the server sent one.
+    DownloadStarted { file: String, total: Option<u64> },
+    /// More of the download for the file arrived. `received` counts every byte so far.
+    DownloadProgress { file: String, received: u64 },
+    /// The file couldn't be brought up to date. The reason is in the [`UpdateSummary`].
+    Failed(String),
+}
+
+type Reporter = Arc<dyn Fn(UpdateEvent) + Send + Sync>;
+
+/// What [`plan_file`] decided for a file, with the size of the download if the CDN reports it.
+type Planned = Result<(PlannedAction, Option<u64>), UpdateError>;
+
+/// How a file in the installation compares to the manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    /// The file matches the manifest.
+    UpToDate,
+    /// The file exists but doesn't match the manifest.
+    Modified,
+    /// The file doesn't exist.
+    Missing,
+}
 
-pub async fn update(options: &Options) -> Result<(), UpdateError> {
-    if !options.install_dir.exists() {
+/// What [`verify`] found.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Every file in the manifest for this platform, sorted by name
+    pub files: Vec<(String, FileStatus)>,
+}
+
+impl VerifyReport {
+    /// The files with the given status.
+    pub fn with_status(&self, status: FileStatus) -> impl Iterator<Item = &str> {
+        self.files
+            .iter()
+            .filter(move |(_, s)| *s == status)
+            .map(|(file, _)| file.as_str())
+    }
+
+    /// Whether every file matches the manifest.
+    pub fn is_intact(&self) -> bool {
+        self.files.iter().all(|(_, s)| *s == FileStatus::UpToDate)
+    }
+}
+
+/// What [`update`] would do to a single file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PlannedAction {
+    /// Nothing, the file already matches the manifest.
+    UpToDate,
+    /// Patch the file with the patch for each hash in `chain` in turn, starting from its
+    /// current hash.
+    Patch { chain: Vec<String> },
+    /// Download the file in full, replacing the one there.
+    Download,
+    /// Download the file in full, as it doesn't exist yet.
+    Missing,
+}
+
+/// What [`update`] would do to a file, and how much it would download to do it.
+#[derive(Debug, Serialize)]
+pub struct PlannedFile {
+    pub file: String,
+    #[serde(flatten)]
+    pub action: PlannedAction,
+    /// Size in bytes of the download, if the CDN reports one.
+    pub download_size: Option<u64>,
+}
+
+/// What [`plan`] found.
+#[derive(Debug, Default, Serialize)]
+pub struct UpdatePlan {
+    /// Every file in the manifest for this platform, sorted by name
+    pub files: Vec<PlannedFile>,
+}
+
+impl UpdatePlan {
+    /// Total size in bytes of every download whose size is known.
+    pub fn download_size(&self) -> u64 {
+        self.files.iter().filter_map(|f| f.download_size).sum()
+    }
+}
+
+/// What an update did to a single file.
+#[derive(Debug)]
+pub enum FileOutcome {
+    /// The file already matched the manifest.
+    UpToDate,
+    /// The file was brought up to date with a patch.
+    Patched,
+    /// The file was downloaded in full.
+    Downloaded,
+    /// The file couldn't be brought up to date.
+    Failed(UpdateError),
+}
+
+/// What an update did to every file it checked.
+#[derive(Debug, Default)]
+pub struct UpdateSummary {
+    /// Every file in the manifest for this platform, sorted by name
+    pub files: Vec<(String, FileOutcome)>,
+    /// Files installed by an earlier update that are no longer in the manifest, sorted by
+    /// name. They were deleted if [`UpdateConfig::prune`] is set.
+    pub obsolete: Vec<String>,
+    /// Obsolete files that couldn't be deleted, with the reason. The next update that prunes
+    /// tries them again, unless they are outside the installation directory.
+    pub prune_failures: Vec<(String, UpdateError)>,
+}
+
+impl UpdateSummary {
+    /// How many files had an outcome matching `pred`.
+    pub fn count<P: Fn(&FileOutcome) -> bool>(&self, pred: P) -> usize {
+        self.files
+            .iter()
+            .filter(|(_, outcome)| pred(outcome))
+            .count()
+    }
+
+    /// The files that failed, with the reason.
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &UpdateError)> {
+        self.files
+            .iter()
+            .filter_map(|(file, outcome)| match outcome {
+                FileOutcome::Failed(err) => Some((file.as_str(), err)),
+                _ => None,
+            })
+    }
+}
+
+/// Brings the installation in `config.install_dir` up to date with the current manifest.
+/// Returns what happened to each file, or [`UpdateError::Incomplete`] if any of them failed.
+pub async fn update<F>(config: &UpdateConfig, on_event: F) -> Result<UpdateSummary, UpdateError>
+where
+    F: Fn(UpdateEvent) + Send + Sync + 'static,
+{
+    config.check_urls()?;
+    let reporter: Reporter = Arc::new(on_event);
+    if !config.install_dir.exists() {
         DirBuilder::new()
             .recursive(true)
-            .create(&options.install_dir)?;
+            .create(&config.install_dir)?;
+    }
+    let mut state = State::load(&config.install_dir);
+    // What the last update verified was against another server's manifest
+    if state.manifest.as_ref().map(|m| m.url.as_str()) != Some(config.manifest_url.as_str()) {
+        state.verified = false;
+    }
+    let fetched = fetch_manifest(config, state.manifest.as_ref()).await?;
+    // Nothing can have changed if every file matched this same manifest last time, as long as
+    // none of them were touched since
+    let skip = fetched.unchanged
+        && state.verified
+        && !config.full_verify
+        && config.mode == UpdateMode::Normal;
+    state.manifest = Some(fetched.cache);
+    let mut files = platform_files(fetched.manifest)?;
+    reporter(UpdateEvent::ManifestFetched { files: files.len() });
+    let names: HashSet<&String> = files.iter().map(|a| &a.0).collect();
+    let obsolete: Vec<String> = state
+        .files
+        .keys()
+        .filter(|file| !names.contains(file))
+        .cloned()
+        .collect();
+    let prune_failures = if config.prune {
+        remove_obsolete(config, &mut state, &obsolete)
+    } else {
+        Vec::new()
+    };
+    let untouched = |a: &(String, FileObject)| {
+        let record = state.files.get(&a.0);
+        record.is_some_and(|r| r.hash == a.1.hash && r.matches(&config.install_dir.join(&a.0)))
+    };
+    if skip && files.iter().all(untouched) {
+        let mut files: Vec<_> = files.into_iter().map(|a| a.0).collect();
+        files.sort();
+        for file in &files {
+            reporter(UpdateEvent::UpToDate(file.clone()));
+        }
+        if config.prune && !obsolete.is_empty() {
+            state.save(&config.install_dir)?;
+        }
+        return Ok(UpdateSummary {
+            files: files
+                .into_iter()
+                .map(|file| (file, FileOutcome::UpToDate))
+                .collect(),
+            obsolete,
+            prune_failures,
+        });
     }
-    let platform_key_string = String::from(PLATFORM_KEY);
-    let manifest: Manifest = fetch_manifest().await?;
+    // Start the biggest files first so they don't hold up the end of the run. The manifest has
+    // no sizes, so missing files count as the biggest and the rest go by their size on disk.
+    files.sort_by_cached_key(|a| Reverse(local_size(&config.install_dir.join(&a.0))));
+    let hashes: HashMap<String, String> = files
+        .iter()
+        .map(|a| (a.0.clone(), a.1.hash.clone()))
+        .collect();
+    // Work out what every file needs before writing anything, so a full disk stops the update
+    // before it starts rather than halfway through
+    let records = &state.files;
+    let plans: Vec<_> = futures::stream::iter(files)
+        .map(|(filename, obj)| async move {
+            let path = config.install_dir.join(&filename);
+            let planned = plan_file(config, &path, &obj, records.get(&filename)).await;
+            (filename, obj, planned)
+        })
+        .buffered(config.jobs.max(1))
+        .collect()
+        .await;
+    check_space(config, &plans)?;
     let handle = tokio::runtime::Handle::current();
-    let threads = manifest
+    let shared = Arc::new(config.clone());
+    let threads = futures::stream::iter(plans)
+        .map(|(filename, obj, planned)| {
+            let action = planned.map(|(action, _)| action);
+            let updater = run_updater(
+                shared.clone(),
+                filename.clone(),
+                obj,
+                action,
+                reporter.clone(),
+            );
+            let task = handle.spawn(updater);
+            async move { (filename, task.await) }
+        })
+        .buffer_unordered(config.jobs.max(1));
+    let summary = UpdateSummary {
+        files: join_updaters(threads, &reporter).await,
+        obsolete,
+        prune_failures,
+    };
+    // Only removed once nothing is left to resume
+    let _ = fs::remove_dir(config.staging_dir());
+    // Remember every file that is now known to be good, so the next run needn't hash it. A
+    // file that failed was left as it was, so its old record still holds.
+    for (file, outcome) in &summary.files {
+        if let FileOutcome::Failed(_) = outcome {
+            continue;
+        }
+        match FileRecord::new(&config.install_dir.join(file), hashes[file].clone()) {
+            Ok(record) => state.files.insert(file.clone(), record),
+            Err(_) => state.files.remove(file),
+        };
+    }
+    state.verified = summary.failures().next().is_none();
+    state.save(&config.install_dir)?;
+    // The engine may have been replaced even if other files failed
+    let engine = crate::launch::engine_path(&config.install_dir);
+    if engine.exists() {
+        set_executable(engine).await?;
+    }
+    if summary.failures().next().is_some() {
+        Err(UpdateError::Incomplete(summary))
+    } else {
+        Ok(summary)
+    }
+}
+
+/// Checks the installation against the current manifest without changing anything.
+pub async fn verify(config: &UpdateConfig) -> Result<VerifyReport, UpdateError> {
+    config.check_urls()?;
+    let state = State::load(&config.install_dir);
+    let fetched = fetch_manifest(config, state.manifest.as_ref()).await?;
+    let checks = platform_files(fetched.manifest)?
+        .into_iter()
+        .map(|(filename, obj)| async move {
+            let status = match local_hash(&config.install_dir.join(&filename)).await? {
+                None => FileStatus::Missing,
+                Some(hash) if hash == obj.hash => FileStatus::UpToDate,
+                Some(_) => FileStatus::Modified,
+            };
+            Ok::<_, UpdateError>((filename, status))
+        });
+    let mut files: Vec<_> = futures::stream::iter(checks)
+        .buffer_unordered(config.jobs.max(1))
+        .try_collect()
+        .await?;
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(VerifyReport { files })
+}
+
+/// Works out what [`update`] would do to each file, without changing anything.
+pub async fn plan(config: &UpdateConfig) -> Result<UpdatePlan, UpdateError> {
+    config.check_urls()?;
+    let state = State::load(&config.install_dir);
+    let fetched = fetch_manifest(config, state.manifest.as_ref()).await?;
+    let records = &state.files;
+    let plans = platform_files(fetched.manifest)?
+        .into_iter()
+        .map(|(filename, obj)| async move {
+            let path = config.install_dir.join(&filename);
+            let (action, download_size) =
+                plan_file(config, &path, &obj, records.get(&filename)).await?;
+            Ok::<_, UpdateError>(PlannedFile {
+                file: filename,
+                action,
+                download_size,
+            })
+        });
+    let mut files: Vec<_> = futures::stream::iter(plans)
+        .buffer_unordered(config.jobs.max(1))
+        .try_collect()
+        .await?;
+    files.sort_by(|a, b| a.file.cmp(&b.file));
+    Ok(UpdatePlan { files })
+}
+
+/// How much bigger a file is assumed to get when decompressed. Bzip2 doesn't record the size,
+/// and the manifest has no sizes either. Game files are mostly already compressed models,
+/// textures and sounds, so they rarely shrink much more than this.
+const DECOMPRESSED_RATIO: u64 = 2;
+
+/// Fails with [`UpdateError::NoSpace`] if the installation's disk can't hold what carrying out
+/// the `plans` writes.
+///
+/// This is an estimate. Files that are missing are assumed to take [`DECOMPRESSED_RATIO`]
+/// times their download, and replaced files to stay the same size. On top of that, each file
+/// needs room while it is updated: its download, a decompressed patch the size of the compressed
+/// one, and the new copy, assumed to be the size of the file it replaces. Only `jobs` files are
+/// updated at once, so only the largest `jobs` of those are counted. Downloads whose size the
+/// CDN won't tell are left out.
+fn check_space(
+    config: &UpdateConfig,
+    plans: &[(String, FileObject, Planned)],
+) -> Result<(), UpdateError> {
+    let mut kept = 0;
+    let mut working = Vec::with_capacity(plans.len());
+    for (filename, _, planned) in plans {
+        let (action, download_size) = match planned {
+            Ok((action, download_size)) => (action, download_size.unwrap_or(0)),
+            Err(_) => continue,
+        };
+        let local = || {
+            fs::metadata(config.install_dir.join(filename))
+                .map(|m| m.len())
+                .unwrap_or(0)
+        };
+        working.push(match action {
+            PlannedAction::UpToDate => 0,
+            PlannedAction::Patch { .. } => 2 * download_size + local(),
+            PlannedAction::Download => download_size + local(),
+            PlannedAction::Missing => {
+                kept += DECOMPRESSED_RATIO * download_size;
+                download_size
+            }
+        });
+    }
+    working.sort_unstable_by_key(|&size| Reverse(size));
+    let required = kept + working.iter().take(config.jobs.max(1)).sum::<u64>();
+    if required == 0 {
+        return Ok(());
+    }
+    // Not every filesystem can tell, in which case the update goes ahead anyway
+    match fs2::available_space(&config.install_dir) {
+        Ok(available) if available < required => Err(UpdateError::NoSpace {
+            required,
+            available,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Size in bytes of the file called `name` on the CDN. Only an estimate, so a mirror that won't
+/// tell just leaves it out.
+async fn remote_size(config: &UpdateConfig, name: &str) -> Option<u64> {
+    config
+        .retry
+        .run(|| download::remote_size(&config.mirrors, name))
+        .await
+        .unwrap_or(None)
+}
+
+/// Total size in bytes of the patches in `chain`, if the CDN reports all of them.
+async fn chain_size(config: &UpdateConfig, obj: &FileObject, chain: &[String]) -> Option<u64> {
+    let mut total = 0;
+    for hash in chain {
+        total += remote_size(config, &obj.patches[hash].filename).await?;
+    }
+    Some(total)
+}
+
+/// The hashes to apply patches to, one after the other, to turn the file with hash `from` into
+/// the one in the manifest. `None` if the patches don't lead there. Only manifests that set
+/// [`PatchObject::target_hash`] have chains longer than one patch.
+fn patch_chain(obj: &FileObject, from: &str) -> Option<Vec<String>> {
+    let mut chain = Vec::new();
+    let mut hash = from;
+    while hash != obj.hash {
+        // A manifest whose patches go round in circles never gets there
+        if chain.iter().any(|h| h == hash) {
+            return None;
+        }
+        let patch = obj.patches.get(hash)?;
+        chain.push(hash.to_string());
+        hash = patch.target(obj);
+    }
+    Some(chain)
+}
+
+/// The manifest entries that apply to this platform.
+/// Fails with [`UpdateError::UnsafeName`] if any of them would be written outside the
+/// installation, staging or cache directory, as manifests can come from any server.
+fn platform_files(manifest: Manifest) -> Result<Vec<(String, FileObject)>, UpdateError> {
+    let files: Vec<_> = manifest
         .into_iter()
-        .filter(|a| a.1.only.contains(&platform_key_string))
-        .map(|a| update_file(options.install_dir.clone(), a.0, a.1))
-        .map(|fut| handle.spawn(fut));
-    join_updaters(threads).await;
-    if let Err(err) = set_executable(options.install_dir.join("TTREngine")).await {
-        eprintln!("Failed to set executable flag!\n{}", err);
-        Err(err)
+        .filter(|a| a.1.only.iter().any(|p| p == PLATFORM_KEY))
+        .collect();
+    for (filename, obj) in &files {
+        let downloads = obj.patches.values().map(|patch| &patch.filename);
+        let names = std::iter::once(filename).chain(std::iter::once(&obj.dl));
+        if let Some(name) = names.chain(downloads).find(|name| !is_relative(name)) {
+            return Err(UpdateError::UnsafeName { name: name.clone() });
+        }
+    }
+    Ok(files)
+}
+
+/// Whether `name` stays inside the directory it is joined to: a relative path without any
+/// `..`.
+fn is_relative(name: &str) -> bool {
+    let mut components = Path::new(name).components().peekable();
+    components.peek().is_some() && components.all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// SHA-1 of the file at `path`, or `None` if it doesn't exist.
+async fn local_hash(path: &Path) -> Result<Option<String>, UpdateError> {
+    if path.exists() {
+        Ok(Some(stream::hash_file(path).await?))
     } else {
-        Ok(())
+        Ok(None)
     }
 }
+
+/// Size of the file at `path`, or `u64::MAX` if it doesn't exist.
+fn local_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(u64::MAX)
+}
+
 #[cfg(unix)]
 async fn set_executable(engine: PathBuf) -> Result<(), UpdateError> {
     use std::os::unix::fs::PermissionsExt;
@@ -57,104 +575,481 @@ async fn set_executable(engine: PathBuf) -> Result<(), UpdateError> {
 }
 
 #[cfg(not(unix))]
-fn set_executable(_: PathBuf) -> Result<(), UpdateError> {
+async fn set_executable(_: PathBuf) -> Result<(), UpdateError> {
     Ok(())
 }
 
-async fn join_updaters<I>(i: I)
+/// Waits for every updater to finish and collects what they did, sorted by file name.
+async fn join_updaters<S>(updaters: S, reporter: &Reporter) -> Vec<(String, FileOutcome)>
 where
-    I: IntoIterator,
-    <I as IntoIterator>::Item: Future,
+    S: Stream<
+        Item = (
+            String,
+            Result<(String, FileOutcome), tokio::task::JoinError>,
+        ),
+    >,
 {
-    futures::future::join_all(i).await;
+    let mut files: Vec<_> = updaters
+        .map(|(filename, result)| match result {
+            Ok(updated) => updated,
+            // One broken file shouldn't take the rest of the update down with it
+            Err(err) => {
+                reporter(UpdateEvent::Failed(filename.clone()));
+                (filename, FileOutcome::Failed(UpdateError::Crashed(err)))
+            }
+        })
+        .collect()
+        .await;
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    files
 }
 
-async fn update_file(dir: PathBuf, filename: String, obj: FileObject) -> Result<(), UpdateError> {
-    let path = dir.join(&filename);
-    if path.exists() {
-        let existing = async_std::fs::read(&path).await?;
-        let hash = sha::sha1::Sha1::default().digest(&existing).to_hex();
-        if obj.hash == hash {
-            // already up to date
-            Ok(())
-        } else {
-            // not up to date
-            // check available patches
-            match obj.patches.get(&hash) {
-                Some(patch) => patch_file(&path, &obj, patch).await,
-                None => download_fresh(&path, &obj).await,
+/// Deletes the `obsolete` files and forgets them. Only files that an earlier update installed
+/// are ever deleted, so screenshots, logs and settings are safe. Returns the files that
+/// couldn't be deleted, which are still remembered so a later update can try again.
+fn remove_obsolete(
+    config: &UpdateConfig,
+    state: &mut State,
+    obsolete: &[String],
+) -> Vec<(String, UpdateError)> {
+    let mut failures = Vec::new();
+    for file in obsolete {
+        // Never leave the installation directory, whatever an old manifest said. Such an
+        // entry can never be removed, so it's reported once and forgotten.
+        if !is_relative(file) {
+            let err = UpdateError::UnsafeName { name: file.clone() };
+            failures.push((file.clone(), err));
+            state.files.remove(file);
+            continue;
+        }
+        match fs::remove_file(config.install_dir.join(file)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                failures.push((file.clone(), err.into()));
+                continue;
             }
+            _ => {}
         }
-    } else {
-        download_fresh(&path, &obj).await
+        state.files.remove(file);
     }
+    failures
 }
 
-async fn download_fresh(file_path: &Path, obj: &FileObject) -> Result<(), UpdateError> {
-    let bytes = download_file(obj.dl.as_str()).await?;
-    let dl_sha = sha1(&bytes).to_hex();
-    if dl_sha == obj.comp_hash {
-        let mut bzd = bzip2::write::BzDecoder::new(File::create(file_path)?);
-        bzd.write_all(bytes.as_slice())?;
-        println!("Downloaded {:?}", file_path.file_name().unwrap());
-        Ok(())
-    } else {
-        Err(UpdateError::Patching)
+/// Updates a single file, reporting how it went.
+async fn run_updater(
+    config: Arc<UpdateConfig>,
+    filename: String,
+    obj: FileObject,
+    action: Result<PlannedAction, UpdateError>,
+    reporter: Reporter,
+) -> (String, FileOutcome) {
+    let updated = match action {
+        Ok(action) => update_file(&config, &filename, &obj, action, &reporter).await,
+        Err(err) => Err(err),
+    };
+    let outcome = match updated {
+        Ok(outcome) => outcome,
+        Err(err) => FileOutcome::Failed(err),
+    };
+    reporter(match outcome {
+        FileOutcome::UpToDate => UpdateEvent::UpToDate(filename.clone()),
+        FileOutcome::Patched => UpdateEvent::Patched(filename.clone()),
+        FileOutcome::Downloaded => UpdateEvent::Downloaded(filename.clone()),
+        FileOutcome::Failed(_) => UpdateEvent::Failed(filename.clone()),
+    });
+    (filename, outcome)
+}
+
+async fn update_file(
+    config: &UpdateConfig,
+    filename: &str,
+    obj: &FileObject,
+    action: PlannedAction,
+    reporter: &Reporter,
+) -> Result<FileOutcome, UpdateError> {
+    let path = config.install_dir.join(filename);
+    match action {
+        PlannedAction::UpToDate => Ok(FileOutcome::UpToDate),
+        PlannedAction::Patch { chain } => {
+            let mut patched = Ok(());
+            for hash in &chain {
+                let patch = &obj.patches[hash];
+                patched = patch_file(config, &path, obj, patch, filename, reporter).await;
+                if patched.is_err() {
+                    break;
+                }
+            }
+            match patched {
+                Ok(()) => Ok(FileOutcome::Patched),
+                // a bad patch can still be recovered from with the full file
+                Err(UpdateError::Patching) | Err(UpdateError::HashMismatch { .. }) => {
+                    download_fresh(config, &path, obj, filename, reporter).await?;
+                    Ok(FileOutcome::Downloaded)
+                }
+                Err(err) => Err(err),
+            }
+        }
+        PlannedAction::Download | PlannedAction::Missing => {
+            download_fresh(config, &path, obj, filename, reporter).await?;
+            Ok(FileOutcome::Downloaded)
+        }
+    }
+}
+
+/// Decides how to bring the file at `path` up to date, and how much that downloads if the CDN
+/// reports it. `record` is what the last update knew about the file.
+async fn plan_file(
+    config: &UpdateConfig,
+    path: &Path,
+    obj: &FileObject,
+    record: Option<&FileRecord>,
+) -> Planned {
+    if config.mode == UpdateMode::Reinstall {
+        let action = if path.exists() {
+            PlannedAction::Download
+        } else {
+            PlannedAction::Missing
+        };
+        return Ok((action, remote_size(config, &obj.dl).await));
+    }
+    // The file can't have changed if it still has the size and modification time it had when
+    // the last update found it matching. Repairs hash everything, as that is what they are for.
+    let trusted = !config.full_verify && config.mode == UpdateMode::Normal;
+    let hash = match record {
+        Some(r) if trusted && r.hash == obj.hash && r.matches(path) => Some(r.hash.clone()),
+        _ => local_hash(path).await?,
+    };
+    let hash = match hash {
+        None => return Ok((PlannedAction::Missing, remote_size(config, &obj.dl).await)),
+        Some(hash) if hash == obj.hash => return Ok((PlannedAction::UpToDate, Some(0))),
+        Some(hash) => hash,
+    };
+    // repairs never patch
+    let chain = match patch_chain(obj, &hash) {
+        Some(chain) if config.mode == UpdateMode::Normal => chain,
+        _ => return Ok((PlannedAction::Download, remote_size(config, &obj.dl).await)),
+    };
+    // Patches can add up to more than the whole file, especially a long chain of them. If the
+    // CDN won't tell, patching is the safer bet.
+    let (patches, full) = futures::join!(
+        chain_size(config, obj, &chain),
+        remote_size(config, &obj.dl)
+    );
+    match (patches, full) {
+        (Some(patches), Some(full)) if patches >= full => Ok((PlannedAction::Download, Some(full))),
+        _ => Ok((PlannedAction::Patch { chain }, patches)),
+    }
+}
+
+async fn download_fresh(
+    config: &UpdateConfig,
+    file_path: &Path,
+    obj: &FileObject,
+    filename: &str,
+    reporter: &Reporter,
+) -> Result<(), UpdateError> {
+    let temp_path = temp_path(file_path);
+    let decompressed = match from_cache(config, &obj.dl, &obj.comp_hash).await {
+        Some(cached) => stream::bunzip_file(&cached, &temp_path).map_err(UpdateError::from),
+        None => config
+            .retry
+            .run(|| {
+                stage_download(
+                    config,
+                    &obj.dl,
+                    &obj.comp_hash,
+                    &temp_path,
+                    filename,
+                    reporter,
+                )
+            })
+            .await
+            .and_then(|(staged, decompressed)| {
+                // Either way the staged download is done with: it matched `comp_hash`
+                keep_in_cache(config, &obj.dl, &staged);
+                Ok(decompressed?)
+            }),
+    };
+    let written = match decompressed {
+        Ok(hash) if hash == obj.hash => Ok(()),
+        Ok(_) => Err(UpdateError::HashMismatch {
+            file: filename.to_string(),
+        }),
+        Err(err) => Err(err),
+    };
+    replace_with(file_path, &temp_path, written)
+}
+
+/// Moves the finished download at `staged` into the cache, or just removes it without one.
+fn keep_in_cache(config: &UpdateConfig, name: &str, staged: &Path) {
+    // Renaming saves reading the download again, unless the cache is on another filesystem
+    store_in_cache(config, name, |temp| {
+        fs::rename(staged, temp).or_else(|_| fs::copy(staged, temp).map(|_| ()))
+    });
+    let _ = fs::remove_file(staged);
+}
+
+/// Path of `name` in the cache directory, if it is there and matches `hash`.
+async fn from_cache(config: &UpdateConfig, name: &str, hash: &str) -> Option<PathBuf> {
+    let path = config.cache_dir.as_ref()?.join(name);
+    match stream::hash_file(&path).await {
+        Ok(cached) if cached == hash => Some(path),
+        _ => None,
+    }
+}
+
+/// Puts a verified download called `name` in the cache directory, with `write` filling in the
+/// file at the path it is given. The cache only saves downloads, so failing to write it
+/// doesn't fail the update.
+fn store_in_cache<W>(config: &UpdateConfig, name: &str, write: W)
+where
+    W: FnOnce(&Path) -> std::io::Result<()>,
+{
+    if let Some(dir) = &config.cache_dir {
+        // Other installations may be reading the cache, so only complete files show up in it.
+        // They may be writing to it too, even from other machines, so the temp file is unique.
+        let path = dir.join(name);
+        let temp_path = path.with_file_name(format!(
+            ".{}.{}.{:08x}.tmp",
+            name,
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        let stored = fs::create_dir_all(dir)
+            .and_then(|_| write(&temp_path))
+            .and_then(|_| fs::rename(&temp_path, &path));
+        if stored.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+    }
+}
+
+/// Downloads the CDN file `name` into the staging directory, picking up where an earlier,
+/// interrupted download left off, and decompresses it into `dest` on the way. Each chunk is
+/// hashed and decompressed as it is written, so the download is never read back; only the part
+/// staged by an earlier run is read, once, to catch up with it. Returns the path of the
+/// complete download once it matches `hash`, with the outcome of decompressing it.
+async fn stage_download(
+    config: &UpdateConfig,
+    name: &str,
+    hash: &str,
+    dest: &Path,
+    filename: &str,
+    reporter: &Reporter,
+) -> Result<(PathBuf, std::io::Result<String>), UpdateError> {
+    let staging_dir = config.staging_dir();
+    fs::create_dir_all(&staging_dir)?;
+    let staged = staging_dir.join(name);
+    loop {
+        let offset = fs::metadata(&staged).map(|m| m.len()).unwrap_or(0);
+        let resumed = Download::resume(&config.mirrors, name, filename, offset, reporter).await?;
+        let mut unpacker = stream::Unpacker::create(dest)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&staged)?;
+        match &resumed {
+            // the server sent the whole file
+            Some(download) if download.received() == 0 => file.set_len(0)?,
+            _ => unpacker.feed(&mut file)?,
+        }
+        if let Some(mut download) = resumed {
+            while let Some(chunk) = download.chunk().await? {
+                file.write_all(chunk.as_ref())?;
+                unpacker.write(chunk.as_ref());
+            }
+        }
+        if unpacker.comp_hash() == hash {
+            return Ok((staged, unpacker.finish()));
+        }
+        fs::remove_file(&staged)?;
+        if offset == 0 {
+            return Err(UpdateError::HashMismatch {
+                file: filename.to_string(),
+            });
+        }
+        // the earlier part was bad, so start over
     }
 }
 
-async fn download_file(name: &str) -> Result<Vec<u8>, UpdateError> {
-    let url = format!("{}{}", CDN_BASE_URL, name);
-    Ok(reqwest::get(&url).await?.bytes().await?.to_vec())
+/// Where a file is written before it is known to be good: next to it, so the final rename
+/// stays on one filesystem.
+fn temp_path(file_path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(file_path.file_name().unwrap_or_default());
+    name.push(".tmp");
+    file_path.with_file_name(name)
+}
+
+/// Moves the temp file over `file_path` once it was `written` successfully, so an interrupted
+/// update never leaves a half written game file behind. Otherwise the temp file is removed.
+fn replace_with(
+    file_path: &Path,
+    temp_path: &Path,
+    written: Result<(), UpdateError>,
+) -> Result<(), UpdateError> {
+    match written {
+        Ok(()) => Ok(fs::rename(temp_path, file_path)?),
+        Err(err) => {
+            let _ = fs::remove_file(temp_path);
+            Err(err)
+        }
+    }
 }
 
-fn prealloc_file(file_path: &Path, size: usize) -> Result<File, UpdateError> {
+fn prealloc_file(file_path: &Path, size: u64) -> Result<File, UpdateError> {
     let file = File::create(file_path)?;
-    file.set_len(size as u64)?;
+    file.set_len(size)?;
     Ok(file)
 }
 
-#[allow(unused)]
+/// Maps the file at `path` into memory, so it can be read as a slice without being loaded.
+fn map_file(path: &Path) -> Result<Mmap, UpdateError> {
+    let file = File::open(path)?;
+    // Safe as long as nothing else changes the file while it is mapped. Game files and staged
+    // patches are only ever replaced by renaming a new file over them, never written in place.
+    Ok(unsafe { Mmap::map(&file)? })
+}
+
 async fn patch_file(
+    config: &UpdateConfig,
     file_path: &Path,
     file_object: &FileObject,
     patch_object: &PatchObject,
+    filename: &str,
+    reporter: &Reporter,
 ) -> Result<(), UpdateError> {
-    let comp_patch = download_file(patch_object.filename.as_str()).await?;
-    let comp_patch_sha = sha1(&comp_patch).to_hex();
-    if comp_patch_sha == patch_object.comp_patch_hash {
-        let size_hint = comp_patch.len();
-        let mut decoder = bzip2::read::BzDecoder::new(std::io::Cursor::new(comp_patch));
-        let mut decomp_patch = Vec::with_capacity(size_hint);
-        decoder
-            .read_to_end(&mut decomp_patch)
-            .map_err(|_| UpdateError::Patching)?;
-        let patch_sha = sha1(&decomp_patch).to_hex();
-        if patch_sha == patch_object.patch_hash {
-            let mut original_data = Vec::with_capacity(fs::metadata(file_path)?.len() as usize);
-            File::open(file_path)?.read_to_end(&mut original_data)?;
-            fs::remove_file(file_path)?;
-            let patcher = qbsdiff::Bspatch::new(decomp_patch.as_slice())
-                .map_err(|_| UpdateError::Patching)?;
-            let mut file = prealloc_file(file_path, patcher.hint_target_size() as usize)?;
-            let final_len = patcher
-                .apply(&original_data, &mut file)
-                .map_err(|_| UpdateError::Patching)?;
-            file.set_len(final_len);
-            Ok(())
-        } else {
-            Err(UpdateError::Patching)
+    let name = patch_object.filename.as_str();
+    let hash = patch_object.comp_patch_hash.as_str();
+    // The patch is decompressed to disk rather than memory, as it can be as big as the file
+    let patch_path = temp_path(&config.staging_dir().join(name));
+    fs::create_dir_all(config.staging_dir())?;
+    let decompressed = match from_cache(config, name, hash).await {
+        Some(cached) => stream::bunzip_file(&cached, &patch_path),
+        None => {
+            let staged = config
+                .retry
+                .run(|| stage_download(config, name, hash, &patch_path, filename, reporter))
+                .await;
+            let (staged, decompressed) = match staged {
+                Ok(staged) => staged,
+                Err(err) => {
+                    let _ = fs::remove_file(&patch_path);
+                    return Err(err);
+                }
+            };
+            keep_in_cache(config, name, &staged);
+            decompressed
+        }
+    };
+    let written = match decompressed {
+        Ok(patch_hash) if patch_hash == patch_object.patch_hash => {
+            let temp_path = temp_path(file_path);
+            let target = patch_object.target(file_object);
+            let written = apply_patch(file_path, &temp_path, &patch_path, target, filename);
+            replace_with(file_path, &temp_path, written.await)
         }
+        _ => Err(UpdateError::Patching),
+    };
+    let _ = fs::remove_file(&patch_path);
+    written
+}
+
+/// Applies the patch at `patch_path` to the file at `file_path`, writing the result to
+/// `temp_path`, which must then have the hash `target`. Both inputs are mapped rather than
+/// read, so memory use stays the same however big the files are.
+async fn apply_patch(
+    file_path: &Path,
+    temp_path: &Path,
+    patch_path: &Path,
+    target: &str,
+    filename: &str,
+) -> Result<(), UpdateError> {
+    let original = map_file(file_path)?;
+    let patch = map_file(patch_path)?;
+    let patcher = qbsdiff::Bspatch::new(&patch).map_err(|_| UpdateError::Patching)?;
+    let mut file = prealloc_file(temp_path, patcher.hint_target_size())?;
+    let final_len = patcher
+        .apply(&original, &mut file)
+        .map_err(|_| UpdateError::Patching)?;
+    file.set_len(final_len)?;
+    // keep the engine executable once the new file is moved over it
+    file.set_permissions(fs::metadata(file_path)?.permissions())?;
+    drop(file);
+    if stream::hash_file(temp_path).await? == target {
+        Ok(())
     } else {
-        Err(UpdateError::Patching)
+        Err(UpdateError::HashMismatch {
+            file: filename.to_string(),
+        })
+    }
+}
+
+/// A freshly fetched manifest.
+struct Fetched {
+    manifest: Manifest,
+    /// What to remember for asking whether the manifest changed next time
+    cache: CachedManifest,
+    /// Whether the server said the manifest is the same as the cached one
+    unchanged: bool,
+}
+
+/// Fetches the manifest, or reuses the `cached` one if the server says it hasn't changed.
+async fn fetch_manifest(
+    config: &UpdateConfig,
+    cached: Option<&CachedManifest>,
+) -> Result<Fetched, UpdateError> {
+    // A manifest from another server says nothing about this one
+    let cached = cached.filter(|cached| cached.url == config.manifest_url);
+    let fetched = config
+        .retry
+        .run(|| fetch_manifest_text(&config.manifest_url, cached))
+        .await?;
+    let (cache, unchanged) = match (fetched, cached) {
+        (Some(cache), _) => (cache, false),
+        (None, Some(cached)) => (cached.clone(), true),
+        (None, None) => unreachable!("Only cached manifests are requested conditionally"),
+    };
+    let manifest: Manifest = serde_json::from_str(cache.text.as_str())?;
+    Ok(Fetched {
+        manifest,
+        cache,
+        unchanged,
+    })
+}
+
+/// Requests the manifest at `url`, unless it is the same as `cached`. Returns `None` if it is.
+async fn fetch_manifest_text(
+    url: &str,
+    cached: Option<&CachedManifest>,
+) -> Result<Option<CachedManifest>, UpdateError> {
+    let mut request = reqwest::Client::new().get(url);
+    if let Some(cached) = cached {
+        if let Some(etag) = &cached.etag {
+            request = request.header(IF_NONE_MATCH, etag.as_str());
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+        }
     }
+    let resp = request.send().await?;
+    if resp.status() == StatusCode::NOT_MODIFIED && cached.is_some() {
+        return Ok(None);
+    }
+    let resp = resp.error_for_status()?;
+    let etag = header(&resp, ETAG);
+    let last_modified = header(&resp, LAST_MODIFIED);
+    Ok(Some(CachedManifest {
+        url: url.to_string(),
+        text: resp.text().await?,
+        etag,
+        last_modified,
+    }))
 }
 
-async fn fetch_manifest() -> Result<Manifest, UpdateError> {
-    let resp = reqwest::get(MANIFEST_URL).await?;
-    let text = resp.text().await?;
-    let m: Manifest = serde_json::from_str(text.as_str())?;
-    Ok(m)
+fn header(resp: &reqwest::Response, name: HeaderName) -> Option<String> {
+    let value = resp.headers().get(name)?;
+    value.to_str().ok().map(str::to_string)
 }
 
 mod manifest {
@@ -181,6 +1076,21 @@ mod manifest {
         pub comp_patch_hash: String,
         #[serde(rename = "patchHash")]
         pub patch_hash: String,
+        /// Hash of the file once this patch is applied, if that isn't the file in the manifest
+        /// yet. Lets a file that is several versions behind be patched one version at a time.
+        ///
+        /// This is an extension for self hosted manifests: the official manifest has no such
+        /// field, as every patch on the official CDN leads straight to the current file. There,
+        /// chains are always a single patch.
+        #[serde(default, rename = "targetHash")]
+        pub target_hash: Option<String>,
+    }
+
+    impl PatchObject {
+        /// Hash of the file once this patch is applied to it.
+        pub fn target<'a>(&'a self, file: &'a FileObject) -> &'a str {
+            self.target_hash.as_deref().unwrap_or(&file.hash)
+        }
     }
 }
 
@@ -189,10 +1099,30 @@ mod error {
 
     #[derive(Debug)]
     pub enum UpdateError {
+        /// The manifest or a mirror URL can't be requested.
+        InvalidUrl(String),
         Downloading(reqwest::Error),
         Parsing(serde_json::Error),
         IO(std::io::Error),
         Patching,
+        /// The file still didn't match the manifest after it was downloaded or patched.
+        HashMismatch {
+            file: String,
+        },
+        /// The manifest names a file outside the directory it belongs in.
+        UnsafeName {
+            name: String,
+        },
+        /// Updating the file panicked. This is a bug.
+        Crashed(tokio::task::JoinError),
+        /// Some files failed to update. The summary holds the reasons.
+        Incomplete(super::UpdateSummary),
+        /// The update needs about `required` bytes of disk space, but only `available` are
+        /// free. No game file was written.
+        NoSpace {
+            required: u64,
+            available: u64,
+        },
     }
 
     impl std::error::Error for UpdateError {}
@@ -206,6 +1136,30 @@ mod error {
                 Self::Parsing(inner) => write!(f, "The web response was malformed: {}", inner),
                 Self::IO(inner) => write!(f, "An IO error occurred: {}", inner),
                 Self::Patching => write!(f, "Error occurred while patching a file"),
+                Self::InvalidUrl(reason) => write!(f, "Invalid URL: {}", reason),
+                Self::UnsafeName { name } => {
+                    write!(f, "The manifest names a file outside the game: {}", name)
+                }
+                Self::Crashed(inner) => write!(f, "Updating the file crashed: {}", inner),
+                Self::HashMismatch { file } => {
+                    write!(f, "{} doesn't match the manifest after updating", file)
+                }
+                Self::Incomplete(summary) => {
+                    write!(f, "Some files failed to update:")?;
+                    for (file, err) in summary.failures() {
+                        write!(f, "\n  {}: {}", file, err)?;
+                    }
+                    Ok(())
+                }
+                Self::NoSpace {
+                    required,
+                    available,
+                } => write!(
+                    f,
+                    "Not enough disk space to update: about {:.1} MB is needed, but only {:.1} MB is free",
+                    *required as f64 / 1e6,
+                    *available as f64 / 1e6
+                ),
             }
         }
     }
@@ -213,7 +1167,7 @@ mod error {
     impl From<reqwest::Error> for UpdateError {
         fn from(err: reqwest::Error) -> Self {
             if err.is_builder() {
-                panic!("The request API was incorrectly called! This is a bug!");
+                return Self::InvalidUrl(err.to_string());
             }
             Self::Downloading(err)
         }
@@ -232,8 +1186,71 @@ mod error {
     }
 }
 
-fn sha1<D: AsRef<[u8]>>(data: D) -> Sha1 {
-    let mut sha = Sha1::default();
-    sha.digest(data.as_ref());
-    sha
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A manifest entry for a file whose current hash is `hash`, with a patch for each
+    /// `(from, to)` pair. A `to` of `None` leads straight to `hash`.
+    fn file(hash: &str, patches: &[(&str, Option<&str>)]) -> FileObject {
+        FileObject {
+            dl: "file.bz2".to_string(),
+            only: vec![PLATFORM_KEY.to_string()],
+            hash: hash.to_string(),
+            comp_hash: String::new(),
+            patches: patches
+                .iter()
+                .map(|&(from, to)| {
+                    let patch = PatchObject {
+                        filename: format!("file.{}.patch.bz2", from),
+                        comp_patch_hash: String::new(),
+                        patch_hash: String::new(),
+                        target_hash: to.map(str::to_string),
+                    };
+                    (from.to_string(), patch)
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn patches_straight_to_the_manifest() {
+        let obj = file("c", &[("a", None), ("b", None)]);
+        assert_eq!(patch_chain(&obj, "a"), Some(vec!["a".to_string()]));
+        assert_eq!(patch_chain(&obj, "b"), Some(vec!["b".to_string()]));
+    }
+
+    #[test]
+    fn follows_patches_through_older_versions() {
+        let obj = file("d", &[("a", Some("b")), ("b", Some("c")), ("c", None)]);
+        let chain = patch_chain(&obj, "a").unwrap();
+        assert_eq!(chain, vec!["a", "b", "c"]);
+        assert_eq!(obj.patches["c"].target(&obj), "d");
+    }
+
+    #[test]
+    fn gives_up_without_a_patch() {
+        let obj = file("d", &[("a", Some("b"))]);
+        assert_eq!(patch_chain(&obj, "x"), None);
+        // leads to a version nothing patches
+        assert_eq!(patch_chain(&obj, "a"), None);
+    }
+
+    #[test]
+    fn gives_up_on_a_cycle() {
+        let obj = file("d", &[("a", Some("b")), ("b", Some("c")), ("c", Some("a"))]);
+        assert_eq!(patch_chain(&obj, "a"), None);
+        assert_eq!(patch_chain(&obj, "b"), None);
+    }
+
+    #[test]
+    fn keeps_names_inside_the_directory() {
+        assert!(is_relative("phase_3.mf"));
+        assert!(is_relative("resources/phase_3.mf"));
+        assert!(!is_relative(""));
+        assert!(!is_relative("../phase_3.mf"));
+        assert!(!is_relative("resources/../../phase_3.mf"));
+        assert!(!is_relative("/etc/passwd"));
+        assert!(!is_relative("./phase_3.mf"));
+    }
 }
@@ -0,0 +1,135 @@
+//! Downloads from the CDN, read a chunk at a time.
+
+use super::{Reporter, UpdateError, UpdateEvent, DEFAULT_CDN_URL};
+use reqwest::header::{CONTENT_LENGTH, RANGE};
+use reqwest::StatusCode;
+
+/// A running download of a CDN file. Progress is reported under the name of the game file it
+/// is for.
+pub struct Download {
+    resp: reqwest::Response,
+    filename: String,
+    reporter: Reporter,
+    received: u64,
+}
+
+impl Download {
+    /// Requests the rest of `name` from the first of `mirrors` that has it, skipping the first
+    /// `offset` bytes. Returns `None` if there is nothing past `offset`. The server may not
+    /// support ranges, so check [`Download::received`] to see where the body starts.
+    pub async fn resume(
+        mirrors: &[String],
+        name: &str,
+        filename: &str,
+        offset: u64,
+        reporter: &Reporter,
+    ) -> Result<Option<Self>, UpdateError> {
+        let resp = request(mirrors, name, offset).await?;
+        match resp.status() {
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(None),
+            StatusCode::PARTIAL_CONTENT => Ok(Some(Self::new(resp, filename, offset, reporter))),
+            _ => Ok(Some(Self::new(resp, filename, 0, reporter))),
+        }
+    }
+
+    fn new(resp: reqwest::Response, filename: &str, offset: u64, reporter: &Reporter) -> Self {
+        reporter(UpdateEvent::DownloadStarted {
+            file: filename.to_string(),
+            total: resp.content_length().map(|len| len + offset),
+        });
+        Self {
+            resp,
+            filename: filename.to_string(),
+            reporter: reporter.clone(),
+            received: offset,
+        }
+    }
+
+    /// How much of the file has been received, counting anything skipped by resuming.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// The next chunk of the body, or `None` once it is complete.
+    pub async fn chunk(&mut self) -> Result<Option<impl AsRef<[u8]>>, UpdateError> {
+        let chunk = self.resp.chunk().await?;
+        if let Some(chunk) = &chunk {
+            self.received += chunk.len() as u64;
+            (self.reporter)(UpdateEvent::DownloadProgress {
+                file: self.filename.clone(),
+                received: self.received,
+            });
+        }
+        Ok(chunk)
+    }
+}
+
+/// Requests `name` from each of `mirrors` in turn, starting at `offset`, until one of them
+/// answers.
+async fn request(
+    mirrors: &[String],
+    name: &str,
+    offset: u64,
+) -> Result<reqwest::Response, UpdateError> {
+    first_answer(mirrors, name, |client, url| {
+        let request = client.get(url);
+        if offset > 0 {
+            request.header(RANGE, format!("bytes={}-", offset))
+        } else {
+            request
+        }
+    })
+    .await
+}
+
+/// Size of `name` on the first of `mirrors` that has it, if the mirror says.
+pub async fn remote_size(mirrors: &[String], name: &str) -> Result<Option<u64>, UpdateError> {
+    let resp = first_answer(mirrors, name, |client, url| client.head(url)).await?;
+    // The body of a HEAD response is empty, so the size has to come from the header itself
+    Ok(resp
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse().ok()))
+}
+
+/// Sends the request made by `build` for `name` to each of `mirrors` in turn, until one of them
+/// answers. Falls back to the official CDN if there are no mirrors.
+async fn first_answer<B>(
+    mirrors: &[String],
+    name: &str,
+    build: B,
+) -> Result<reqwest::Response, UpdateError>
+where
+    B: Fn(&reqwest::Client, &str) -> reqwest::RequestBuilder,
+{
+    let official = [DEFAULT_CDN_URL.to_string()];
+    let mirrors = if mirrors.is_empty() {
+        &official[..]
+    } else {
+        mirrors
+    };
+    let client = reqwest::Client::new();
+    let mut last_err = None;
+    for mirror in mirrors {
+        let result = match build(&client, &cdn_url(mirror, name)).send().await {
+            Ok(resp) if resp.status() == StatusCode::RANGE_NOT_SATISFIABLE => return Ok(resp),
+            Ok(resp) => resp.error_for_status(),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(resp) => return Ok(resp),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.expect("There is always a mirror").into())
+}
+
+/// URL of `name` on the mirror at `base`, which may leave out the trailing slash.
+fn cdn_url(base: &str, name: &str) -> String {
+    if base.ends_with('/') {
+        format!("{}{}", base, name)
+    } else {
+        format!("{}/{}", base, name)
+    }
+}
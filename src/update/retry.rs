@@ -0,0 +1,163 @@
+//! Retrying requests to the CDN that failed for reasons that may go away by themselves.
+
+use super::UpdateError;
+use rand::Rng;
+use std::future::Future;
+use std::time::Duration;
+
+/// HTTP statuses that usually mean the CDN is briefly overloaded or restarting. These are
+/// retried unless configured otherwise.
+pub const RETRIABLE_STATUSES: [u16; 6] = [408, 429, 500, 502, 503, 504];
+
+/// How failed requests to the CDN are retried. Delays double after every attempt, up to
+/// `max_delay`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// How many times a request is tried in total. `1` disables retrying.
+    pub attempts: u32,
+    /// Delay before the first retry
+    pub base_delay: Duration,
+    /// Longest delay between two attempts
+    pub max_delay: Duration,
+    /// Randomizes each delay between half and all of it, so that several downloads that failed
+    /// together don't retry in lockstep.
+    pub jitter: bool,
+    /// HTTP statuses worth retrying
+    pub statuses: Vec<u16>,
+    /// Retries requests that timed out
+    pub timeouts: bool,
+    /// Retries requests that got no answer at all, such as dropped or refused connections
+    pub connection_errors: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+            statuses: RETRIABLE_STATUSES.to_vec(),
+            timeouts: true,
+            connection_errors: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Runs `op` until it succeeds, fails in a way that isn't worth retrying, or runs out of
+    /// attempts.
+    pub(crate) async fn run<T, F, Fut>(&self, mut op: F) -> Result<T, UpdateError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, UpdateError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Err(err) if attempt < self.attempts && self.is_retriable(&err) => {
+                    tokio::time::delay_for(self.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Delay after the given failed attempt, counting from 1.
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt - 1);
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        if self.jitter {
+            delay.mul_f64(rand::thread_rng().gen_range(0.5, 1.0))
+        } else {
+            delay
+        }
+    }
+
+    /// Whether `err` is one of the failures this policy retries. Anything but a failed request,
+    /// such as a bad URL or a file that didn't match, would only fail the same way again.
+    fn is_retriable(&self, err: &UpdateError) -> bool {
+        match err {
+            UpdateError::Downloading(err) => match err.status() {
+                Some(status) => self.statuses.contains(&status.as_u16()),
+                None if err.is_timeout() => self.timeouts,
+                None => self.connection_errors && !(err.is_builder() || err.is_redirect()),
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            jitter: false,
+            ..RetryPolicy::default()
+        }
+    }
+
+    fn status_error(status: u16) -> UpdateError {
+        let response = http::Response::builder().status(status).body("").unwrap();
+        let response = reqwest::Response::from(response);
+        UpdateError::Downloading(response.error_for_status().unwrap_err())
+    }
+
+    #[test]
+    fn doubles_up_to_the_longest_delay() {
+        let policy = policy();
+        let delays: Vec<u64> = (1..=6).map(|a| policy.delay(a).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn survives_any_number_of_attempts() {
+        let policy = policy();
+        assert_eq!(policy.delay(40), policy.max_delay);
+        assert_eq!(policy.delay(u32::MAX), policy.max_delay);
+    }
+
+    #[test]
+    fn jitters_between_half_and_all_of_the_delay() {
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy()
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(3);
+            assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(4));
+        }
+    }
+
+    #[test]
+    fn retries_configured_statuses() {
+        let policy = policy();
+        assert!(policy.is_retriable(&status_error(503)));
+        assert!(!policy.is_retriable(&status_error(404)));
+        let policy = RetryPolicy {
+            statuses: vec![404],
+            ..policy
+        };
+        assert!(policy.is_retriable(&status_error(404)));
+        assert!(!policy.is_retriable(&status_error(503)));
+    }
+
+    #[test]
+    fn never_retries_what_would_fail_again() {
+        let policy = policy();
+        let err = reqwest::Client::new().get("not a url").build().unwrap_err();
+        assert!(!policy.is_retriable(&UpdateError::Downloading(err)));
+        let err = UpdateError::HashMismatch {
+            file: "phase_3.mf".to_string(),
+        };
+        assert!(!policy.is_retriable(&err));
+    }
+}
@@ -0,0 +1,89 @@
+//! What the launcher remembers about an installation between runs.
+
+use super::{temp_path, UpdateError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Name of the state file inside the installation directory.
+const STATE_FILE: &str = ".launcher-state.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// The manifest the last update used.
+    #[serde(default)]
+    pub manifest: Option<CachedManifest>,
+    /// Whether the last update found every file matching `manifest`.
+    #[serde(default)]
+    pub verified: bool,
+    /// The game files the launcher has checked or written, by name.
+    #[serde(default)]
+    pub files: BTreeMap<String, FileRecord>,
+}
+
+/// A game file as it was when it was last found to hash to `hash`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileRecord {
+    pub size: u64,
+    pub modified: SystemTime,
+    pub hash: String,
+}
+
+impl FileRecord {
+    /// Records the file at `path`, which hashes to `hash`.
+    pub fn new(path: &Path, hash: String) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Self {
+            size: metadata.len(),
+            modified: metadata.modified()?,
+            hash,
+        })
+    }
+
+    /// Whether the file at `path` still has the recorded size and modification time.
+    pub fn matches(&self, path: &Path) -> bool {
+        match fs::metadata(path) {
+            Ok(metadata) => {
+                metadata.len() == self.size && metadata.modified().ok() == Some(self.modified)
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// A manifest as the server sent it, with the validators for asking whether it changed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedManifest {
+    /// Where the manifest came from. The validators mean nothing to any other server.
+    #[serde(default)]
+    pub url: String,
+    pub text: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl State {
+    /// Reads the state of the installation in `install_dir`. A missing or unreadable state file
+    /// is an empty state, which only costs a slower update.
+    pub fn load(install_dir: &Path) -> Self {
+        fs::read_to_string(path(install_dir))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, install_dir: &Path) -> Result<(), UpdateError> {
+        let path = path(install_dir);
+        let temp_path = temp_path(&path);
+        fs::write(&temp_path, serde_json::to_string(self)?)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+}
+
+fn path(install_dir: &Path) -> PathBuf {
+    install_dir.join(STATE_FILE)
+}
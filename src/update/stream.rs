@@ -0,0 +1,244 @@
+//! Incremental hashing and decompression, so files never have to be held in memory whole.
+
+use bzip2::{Decompress, Status};
+use sha1::Sha1;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// SHA-1 of the file at `path`, read a block at a time.
+pub async fn hash_file(path: &Path) -> io::Result<String> {
+    use async_std::io::ReadExt;
+    let mut file = async_std::fs::File::open(path).await?;
+    let mut hasher = Sha1::new();
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(hasher.digest().to_string());
+        }
+        hasher.update(&buf[..read]);
+    }
+}
+
+/// Decompresses the bzip2 file at `src` into `dest`, returning the SHA-1 of the output.
+pub fn bunzip_file(src: &Path, dest: &Path) -> io::Result<String> {
+    let mut unpacker = Unpacker::create(dest)?;
+    unpacker.feed(&mut File::open(src)?)?;
+    unpacker.finish()
+}
+
+/// Decompresses a bzip2 file into another file as it arrives, hashing both the compressed data
+/// and the output on the way, so a download is never read back.
+pub struct Unpacker {
+    comp_hasher: Sha1,
+    decoder: Bunzip<HashWriter<BufWriter<File>>>,
+    /// Why decompressing failed. The compressed data is still hashed after that, as its hash
+    /// tells a bad download from a bad file.
+    failed: Option<io::Error>,
+}
+
+impl Unpacker {
+    /// Starts decompressing into a new file at `dest`.
+    pub fn create(dest: &Path) -> io::Result<Self> {
+        Ok(Self {
+            comp_hasher: Sha1::new(),
+            decoder: Bunzip::new(HashWriter::new(BufWriter::new(File::create(dest)?))),
+            failed: None,
+        })
+    }
+
+    /// Takes the next piece of the compressed file.
+    pub fn write(&mut self, piece: &[u8]) {
+        self.comp_hasher.update(piece);
+        if self.failed.is_none() {
+            self.failed = self.decoder.write(piece).err();
+        }
+    }
+
+    /// Takes everything left in `input`.
+    pub fn feed<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+        let mut buf = vec![0; BUFFER_SIZE];
+        loop {
+            let read = input.read(&mut buf)?;
+            if read == 0 {
+                return Ok(());
+            }
+            self.write(&buf[..read]);
+        }
+    }
+
+    /// SHA-1 of the compressed data so far.
+    pub fn comp_hash(&self) -> String {
+        self.comp_hasher.digest().to_string()
+    }
+
+    /// Finishes the output file, returning its SHA-1.
+    pub fn finish(self) -> io::Result<String> {
+        if let Some(err) = self.failed {
+            return Err(err);
+        }
+        let (file, hash) = self.decoder.finish()?.finish();
+        file.into_inner().map_err(|err| err.into_error())?;
+        Ok(hash)
+    }
+}
+
+/// Hashes everything written through it.
+pub struct HashWriter<W> {
+    inner: W,
+    hasher: Sha1,
+}
+
+impl<W: Write> HashWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha1::new(),
+        }
+    }
+
+    /// Returns the inner writer and the hash of everything written.
+    pub fn finish(self) -> (W, String) {
+        (self.inner, self.hasher.digest().to_string())
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decompresses a bzip2 stream fed to it piece by piece into a writer.
+pub struct Bunzip<W> {
+    data: Decompress,
+    out: W,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl<W: Write> Bunzip<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            data: Decompress::new(false),
+            out,
+            buf: Vec::with_capacity(BUFFER_SIZE),
+            done: false,
+        }
+    }
+
+    /// Decompresses the next piece of the stream. Anything after the end of the stream is ignored.
+    pub fn write(&mut self, mut input: &[u8]) -> io::Result<()> {
+        while !self.done {
+            self.buf.clear();
+            let before = self.data.total_in();
+            let status = self
+                .data
+                .decompress_vec(input, &mut self.buf)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            input = &input[(self.data.total_in() - before) as usize..];
+            self.out.write_all(&self.buf)?;
+            self.done = status == Status::StreamEnd;
+            // A full buffer means there may be more output pending, even without more input
+            if input.is_empty() && self.buf.len() < self.buf.capacity() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the writer, failing if the stream ended early.
+    pub fn finish(self) -> io::Result<W> {
+        if self.done {
+            Ok(self.out)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "bzip2 stream ended early",
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bzip2::write::BzEncoder;
+    use bzip2::Compression;
+
+    /// Compressible enough that a small piece of input fills the output buffer several times.
+    fn data() -> Vec<u8> {
+        (0..300_000u32)
+            .map(|i| (i % 251) as u8 ^ (i / 1000) as u8)
+            .collect()
+    }
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = BzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn bunzip(compressed: &[u8], piece: usize) -> io::Result<Vec<u8>> {
+        let mut decoder = Bunzip::new(Vec::new());
+        for chunk in compressed.chunks(piece) {
+            decoder.write(chunk)?;
+        }
+        decoder.finish()
+    }
+
+    #[test]
+    fn round_trips_in_any_pieces() {
+        let data = data();
+        let compressed = compress(&data);
+        for &piece in &[1, 7, 4096, compressed.len()] {
+            assert_eq!(
+                bunzip(&compressed, piece).unwrap(),
+                data,
+                "pieces of {}",
+                piece
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_data_after_the_stream() {
+        let data = data();
+        let mut compressed = compress(&data);
+        compressed.extend_from_slice(b"trailing garbage");
+        assert_eq!(bunzip(&compressed, 4096).unwrap(), data);
+    }
+
+    #[test]
+    fn fails_on_a_truncated_stream() {
+        let compressed = compress(&data());
+        let err = bunzip(&compressed[..compressed.len() / 2], 4096).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn fails_on_corrupt_data() {
+        let mut compressed = compress(&data());
+        compressed[100] ^= 0xff;
+        assert!(bunzip(&compressed, 4096).is_err());
+    }
+
+    #[test]
+    fn hashes_what_it_writes() {
+        let data = data();
+        let mut writer = HashWriter::new(Vec::new());
+        writer.write_all(&data).unwrap();
+        let (written, hash) = writer.finish();
+        assert_eq!(written, data);
+        assert_eq!(hash, Sha1::from(&data).digest().to_string());
+    }
+}